@@ -1,6 +1,7 @@
 /// LpBound provides a guaranteed upper bound on query output size, making it useful for some use cases
 
 use std::collections::HashMap;
+use std::fmt;
 
 /// A degree sequence is a sorted list of frequencies of values in a column
 #[derive(Debug, Clone)]
@@ -37,6 +38,27 @@ impl DegreeSequence {
         sum.powf(1.0 / p)
     }
 
+    /// Calculate the ℓp-norm by summing chunks of the degree sequence on all available cores
+    pub fn lp_norm_parallel(&self, p: f64) -> f64 {
+        if p == f64::INFINITY {
+            return self.max_degree() as f64;
+        }
+
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        if threads == 1 {
+            return self.lp_norm(p);
+        }
+        let chunk_len = self.degrees.len().div_ceil(threads).max(1);
+        let sum: f64 = std::thread::scope(|scope| {
+            let partials: Vec<_> = self.degrees.chunks(chunk_len)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(|&d| (d as f64).powf(p)).sum::<f64>()))
+                .collect();
+            partials.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        sum.powf(1.0 / p)
+    }
+
     /// Get the cardinality (ℓ1-norm)
     pub fn cardinality(&self) -> usize {
         self.degrees.iter().sum()
@@ -46,8 +68,231 @@ impl DegreeSequence {
     pub fn max_degree(&self) -> usize {
         *self.degrees.first().unwrap_or(&0)
     }
+
+    /// Get the number of distinct values (length of the sequence)
+    pub fn ndv(&self) -> usize {
+        self.degrees.len()
+    }
+
+    /// Get the degree at the p-th percentile (p in [0, 1]) using the nearest-rank method
+    pub fn percentile(&self, p: f64) -> usize {
+        if self.degrees.is_empty() {
+            return 0;
+        }
+
+        // Degrees are sorted in descending order, so rank k from the bottom sits at len - k
+        let n = self.degrees.len();
+        let rank = ((p.clamp(0.0, 1.0) * n as f64).ceil() as usize).max(1);
+        self.degrees[n - rank]
+    }
+
+    /// Get the median degree
+    pub fn median(&self) -> usize {
+        self.percentile(0.5)
+    }
+
+    /// Whether the 99th-percentile degree exceeds the median degree by more than `threshold` times
+    pub fn is_skewed(&self, threshold: f64) -> bool {
+        let median = self.median();
+        median > 0 && self.percentile(0.99) as f64 / median as f64 > threshold
+    }
+
+    /// Split into heavy hitters (degree above `threshold`) and light values
+    pub fn split_heavy_light(&self, threshold: usize) -> (DegreeSequence, DegreeSequence) {
+        let split = self.degrees.iter().take_while(|&&d| d > threshold).count();
+        (
+            DegreeSequence { degrees: self.degrees[..split].to_vec() },
+            DegreeSequence { degrees: self.degrees[split..].to_vec() },
+        )
+    }
+
+    /// Scale every degree by a filter selectivity in [0, 1], dropping values that no longer occur
+    pub fn apply_selectivity(&self, sel: f64) -> DegreeSequence {
+        let sel = sel.clamp(0.0, 1.0);
+        let mut degrees: Vec<usize> = self.degrees.iter()
+            .map(|&d| (d as f64 * sel).round() as usize)
+            .filter(|&d| d > 0)
+            .collect();
+        degrees.sort_by(|a, b| b.cmp(a));
+
+        Self { degrees }
+    }
+}
+
+impl fmt::Display for DegreeSequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.cardinality() as f64;
+        let mut lines = Vec::new();
+
+        // Degrees are sorted, so equal degrees form consecutive runs
+        let mut i = 0;
+        while i < self.degrees.len() {
+            let degree = self.degrees[i];
+            let count = self.degrees[i..].iter().take_while(|&&d| d == degree).count();
+            let share = (degree * count) as f64 / total * 100.0;
+            let noun = if count == 1 { "value" } else { "values" };
+            lines.push(format!("degree {}: {} {} ({:.1}%)", degree, count, noun, share));
+            i += count;
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Merge the degree sequences of two UNION ALL inputs by adding degrees of equal rank
+///
+/// Pairing the i-th largest degree of each side is an upper bound for every ℓp-norm of the
+/// true merged sequence, so bounds derived from the result stay pessimistic.
+pub fn merge_degree_sequences_union_all(a: &DegreeSequence, b: &DegreeSequence) -> DegreeSequence {
+    let len = a.degrees.len().max(b.degrees.len());
+    let degrees = (0..len)
+        .map(|i| a.degrees.get(i).unwrap_or(&0) + b.degrees.get(i).unwrap_or(&0))
+        .collect();
+
+    DegreeSequence { degrees }
+}
+
+/// Append `value` as a LEB128 varint
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Read a LEB128 varint at `*pos`, or None if the input ends first or the value overflows
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        let bits = (byte & 0x7f) as usize;
+        if shift >= usize::BITS || (bits << shift) >> shift != bits {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
 }
 
+/// Bytes that `serialize_compressed` cannot have written
+#[derive(Debug, PartialEq)]
+pub struct CorruptSequence;
+
+impl fmt::Display for CorruptSequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "corrupt compressed degree sequence")
+    }
+}
+
+impl std::error::Error for CorruptSequence {}
+
+/// Compress a degree sequence into runs of equal degrees
+///
+/// Each run is stored as two varints: the drop from the previous run's degree (the first run
+/// stores its degree as is) and the run length. Real degree sequences are dominated by long
+/// runs of small degrees, so this is far smaller than one machine word per value.
+pub fn serialize_compressed(seq: &DegreeSequence) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut prev = None;
+    let mut i = 0;
+    while i < seq.degrees.len() {
+        let degree = seq.degrees[i];
+        let count = seq.degrees[i..].iter().take_while(|&&d| d == degree).count();
+        write_varint(&mut out, prev.map_or(degree, |p: usize| p - degree));
+        write_varint(&mut out, count);
+        prev = Some(degree);
+        i += count;
+    }
+    out
+}
+
+/// Decompress a degree sequence written by `serialize_compressed`
+pub fn deserialize_compressed(bytes: &[u8]) -> Result<DegreeSequence, CorruptSequence> {
+    let degrees = CompressedDegrees::new(bytes).collect::<Result<Vec<usize>, CorruptSequence>>()?;
+    Ok(DegreeSequence { degrees })
+}
+
+/// Iterates the degrees of a compressed sequence, decoding one run at a time
+pub struct CompressedDegrees<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    degree: Option<usize>,
+    remaining: usize,
+    corrupt: bool,
+}
+
+impl<'a> CompressedDegrees<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0, degree: None, remaining: 0, corrupt: false }
+    }
+
+    /// Decode the next run as (degree, count); after an error the iterator is exhausted
+    pub fn next_run(&mut self) -> Option<Result<(usize, usize), CorruptSequence>> {
+        if self.corrupt || self.pos == self.bytes.len() {
+            return None;
+        }
+
+        let delta = read_varint(self.bytes, &mut self.pos);
+        let count = read_varint(self.bytes, &mut self.pos);
+        // Degrees only drop from run to run, and the encoder never writes an empty run
+        let degree = match (self.degree, delta) {
+            (None, delta) => delta,
+            (Some(prev), Some(delta)) => prev.checked_sub(delta),
+            (Some(_), None) => None,
+        };
+        match (degree, count) {
+            (Some(degree), Some(count)) if count > 0 => {
+                self.degree = Some(degree);
+                Some(Ok((degree, count)))
+            }
+            _ => {
+                self.corrupt = true;
+                Some(Err(CorruptSequence))
+            }
+        }
+    }
+}
+
+impl Iterator for CompressedDegrees<'_> {
+    type Item = Result<usize, CorruptSequence>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining == 0 {
+            match self.next_run()? {
+                Ok((_, count)) => self.remaining = count,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.remaining -= 1;
+        self.degree.map(Ok)
+    }
+}
+
+/// Calculate the ℓp-norm of a compressed degree sequence run by run, without decompressing it
+pub fn lp_norm_compressed(bytes: &[u8], p: f64) -> Result<f64, CorruptSequence> {
+    let mut runs = CompressedDegrees::new(bytes);
+    let (mut max, mut sum) = (0, 0.0);
+    while let Some(run) = runs.next_run() {
+        let (degree, count) = run?;
+        max = max.max(degree);
+        sum += count as f64 * (degree as f64).powf(p);
+    }
+
+    if p == f64::INFINITY {
+        return Ok(max as f64);
+    }
+    Ok(sum.powf(1.0 / p))
+}
+
+/// Degree sequences longer than this have their ℓp-norms computed in parallel
+const PARALLEL_LP_NORM_THRESHOLD: usize = 100_000;
+
 /// A relation with statistics for cardinality estimation
 #[derive(Debug)]
 pub struct Relation {
@@ -55,6 +300,16 @@ pub struct Relation {
     attributes: Vec<String>,
     degree_sequences: HashMap<String, DegreeSequence>,
     lp_norms: HashMap<(String, usize), f64>, // (attribute, p) -> ℓp-norm
+    null_fractions: HashMap<String, f64>,
+    correlations: Vec<CorrelationStats>,
+}
+
+/// Pairwise correlation (Pearson or rank) between two attributes of a relation
+#[derive(Debug, Clone)]
+pub struct CorrelationStats {
+    pub col1: String,
+    pub col2: String,
+    pub correlation: f64,
 }
 
 impl Relation {
@@ -64,15 +319,18 @@ impl Relation {
             attributes: attributes.iter().map(|s| s.to_string()).collect(),
             degree_sequences: HashMap::new(),
             lp_norms: HashMap::new(),
+            null_fractions: HashMap::new(),
+            correlations: Vec::new(),
         }
     }
 
     /// Add a degree sequence for an attribute
     pub fn add_degree_sequence(&mut self, attr: &str, seq: DegreeSequence) {
         // Pre-compute ℓp-norms for p ∈ {1, 2, 3, 4, ∞}
+        let parallel = seq.degrees.len() > PARALLEL_LP_NORM_THRESHOLD;
         let ps = vec![1, 2, 3, 4];
-        for p in ps.iter() {
-            let norm = seq.lp_norm(p as f64);
+        for &p in ps.iter() {
+            let norm = if parallel { seq.lp_norm_parallel(p as f64) } else { seq.lp_norm(p as f64) };
             self.lp_norms.insert((attr.to_string(), p), norm);
         }
 
@@ -87,16 +345,349 @@ impl Relation {
     pub fn get_lp_norm(&self, attr: &str, p: usize) -> Option<f64> {
         self.lp_norms.get(&(attr.to_string(), p)).cloned()
     }
+
+    /// Record the fraction of an attribute's values that are NULL
+    pub fn add_null_fraction(&mut self, attr: &str, fraction: f64) {
+        self.null_fractions.insert(attr.to_string(), fraction.clamp(0.0, 1.0));
+    }
+
+    /// Fraction of an attribute's values that are not NULL (1.0 when unknown)
+    pub fn non_null_fraction(&self, attr: &str) -> f64 {
+        1.0 - self.null_fractions.get(attr).cloned().unwrap_or(0.0)
+    }
+
+    /// Degree sequence of an attribute after a filter with selectivity `sel` on it. A predicate
+    /// on the attribute never selects NULLs, so only the non-NULL share of the tuples survives.
+    pub fn apply_selectivity(&self, attr: &str, sel: f64) -> Option<DegreeSequence> {
+        let seq = self.degree_sequences.get(attr)?;
+        Some(seq.apply_selectivity(sel * self.non_null_fraction(attr)))
+    }
+
+    /// Record the correlation between two attributes
+    pub fn add_correlation(&mut self, stats: CorrelationStats) {
+        self.correlations.push(stats);
+    }
+
+    /// Get the correlation between two attributes, in either order (0.0 when unknown)
+    pub fn correlation(&self, attr1: &str, attr2: &str) -> f64 {
+        self.correlations.iter()
+            .find(|c| (c.col1 == attr1 && c.col2 == attr2) || (c.col1 == attr2 && c.col2 == attr1))
+            .map(|c| c.correlation)
+            .unwrap_or(0.0)
+    }
+
+    /// Get the number of tuples. Attributes loaded from separate statistics may disagree, so the
+    /// largest total over all attributes is taken to stay an upper bound.
+    pub fn cardinality(&self) -> Option<usize> {
+        self.degree_sequences.values().map(|seq| seq.cardinality()).max()
+    }
+
+    /// Upper bound on distinct tuples: the product of per-attribute NDVs, capped by the cardinality
+    pub fn ndv(&self) -> Option<usize> {
+        let cardinality = self.cardinality()?;
+        let product = self.degree_sequences.values()
+            .fold(1usize, |acc, seq| acc.saturating_mul(seq.ndv()));
+        Some(product.min(cardinality))
+    }
+
+    /// Build the statistics of `self UNION ALL other`, keeping the attributes both sides share
+    pub fn union_all(&self, name: &str, other: &Relation) -> Relation {
+        let attributes: Vec<&str> = self.attributes.iter()
+            .filter(|attr| other.attributes.contains(attr))
+            .map(|attr| attr.as_str())
+            .collect();
+        let mut result = Relation::new(name, attributes.clone());
+
+        for attr in attributes {
+            if let (Some(a), Some(b)) = (self.degree_sequences.get(attr), other.degree_sequences.get(attr)) {
+                result.add_degree_sequence(attr, merge_degree_sequences_union_all(a, b));
+            }
+        }
+
+        result
+    }
 }
 
-/// Simple representation of a join query
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.name, self.attributes.join(", "))?;
+
+        for attr in &self.attributes {
+            if !self.degree_sequences.contains_key(attr) {
+                continue;
+            }
+            let norm = |p| self.get_lp_norm(attr, p).unwrap_or(0.0);
+            write!(
+                f,
+                "\n  {}: ℓ1={:.2} ℓ2={:.2} ℓ3={:.2} ℓ4={:.2} ℓ∞={:.2}",
+                attr, norm(1), norm(2), norm(3), norm(4), norm(0)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Errors raised while loading statistics from a file
 #[derive(Debug)]
+pub enum StatsError {
+    /// The file could not be read
+    Io(std::io::Error),
+    /// A line could not be parsed
+    Parse { line: usize, message: String },
+    /// The header lacks a required column
+    MissingColumn(String),
+}
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatsError::Io(err) => write!(f, "cannot read statistics: {}", err),
+            StatsError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            StatsError::MissingColumn(column) => write!(f, "missing column: {}", column),
+        }
+    }
+}
+
+impl std::error::Error for StatsError {}
+
+impl From<std::io::Error> for StatsError {
+    fn from(err: std::io::Error) -> Self {
+        StatsError::Io(err)
+    }
+}
+
+/// Split one CSV line into trimmed fields. A field may be wrapped in double quotes to contain
+/// commas, with `""` standing for a literal quote.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated quoted field".to_string()),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_some_and(|&c| c != ',') {
+                return Err("unexpected text after quoted field".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                field.push(c);
+            }
+            field.truncate(field.trim_end().len());
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+/// Load a relation from a CSV of value frequencies with header `attr_name,value,frequency`,
+/// as exported from pg_stats and similar. The relation is named after the file stem. Values
+/// containing commas must be quoted; quoted fields cannot span lines.
+pub fn load_relation_from_csv(path: &str) -> Result<Relation, StatsError> {
+    let content = std::fs::read_to_string(path)?;
+    let mut lines = content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    let parse = |i: usize, line: &str| {
+        split_csv_line(line).map_err(|message| StatsError::Parse { line: i + 1, message })
+    };
+
+    let header = match lines.next() {
+        Some((i, line)) => parse(i, line)?,
+        None => Vec::new(),
+    };
+    let column = |name: &str| header.iter().position(|h| h == name)
+        .ok_or_else(|| StatsError::MissingColumn(name.to_string()));
+    let (attr_idx, value_idx, freq_idx) = (column("attr_name")?, column("value")?, column("frequency")?);
+
+    // attribute -> value -> frequency, keeping attributes in order of first appearance
+    let mut attributes: Vec<String> = Vec::new();
+    let mut frequencies: HashMap<String, HashMap<String, usize>> = HashMap::new();
+    for (i, line) in lines {
+        let fields = parse(i, line)?;
+        if fields.len() != header.len() {
+            return Err(StatsError::Parse {
+                line: i + 1,
+                message: format!(
+                    "expected {} fields, found {} (quote values that contain commas)",
+                    header.len(),
+                    fields.len()
+                ),
+            });
+        }
+        let field = |idx: usize| fields[idx].as_str();
+
+        let attr = field(attr_idx);
+        let frequency: usize = field(freq_idx).parse().map_err(|e| StatsError::Parse {
+            line: i + 1,
+            message: format!("invalid frequency: {}", e),
+        })?;
+
+        if !frequencies.contains_key(attr) {
+            attributes.push(attr.to_string());
+        }
+        *frequencies.entry(attr.to_string()).or_default()
+            .entry(field(value_idx).to_string()).or_insert(0) += frequency;
+    }
+
+    let name = std::path::Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or(path);
+    let mut relation = Relation::new(name, attributes.iter().map(|a| a.as_str()).collect());
+    for attr in &attributes {
+        let mut degrees: Vec<usize> = frequencies[attr].values().cloned().filter(|&f| f > 0).collect();
+        degrees.sort_by(|a, b| b.cmp(a));
+        relation.add_degree_sequence(attr, DegreeSequence { degrees });
+    }
+
+    Ok(relation)
+}
+
+/// Simple representation of a join query
+#[derive(Debug, PartialEq)]
 pub struct JoinQuery {
     relations: Vec<String>,
     join_conditions: Vec<(String, String, String, String)>, // (rel1, attr1, rel2, attr2)
     group_by: Vec<(String, String)>, // (relation, attribute)
 }
 
+impl JoinQuery {
+    /// Whether both sides of the join are the same relation, e.g. `R r1 JOIN R r2`
+    pub fn is_self_join(&self) -> bool {
+        self.relations.len() == 2 && self.relations[0] == self.relations[1]
+    }
+}
+
+/// Tightest of the two-way q-inequality bounds for joining two degree sequences, where at most
+/// `non_null_a` and `non_null_b` tuples of each side have a non-NULL key
+fn sequence_join_bound(a: &DegreeSequence, b: &DegreeSequence, non_null_a: f64, non_null_b: f64) -> f64 {
+    let card_a = (a.cardinality() as f64).min(non_null_a);
+    let card_b = (b.cardinality() as f64).min(non_null_b);
+    let (max_a, max_b) = (a.max_degree() as f64, b.max_degree() as f64);
+    [card_a * card_b, card_a * max_b, max_a * card_b, a.lp_norm(2.0) * b.lp_norm(2.0)]
+        .iter().cloned().fold(f64::INFINITY, f64::min)
+}
+
+/// Errors reported by LpBound
+#[derive(Debug, PartialEq)]
+pub enum LpBoundError {
+    /// A relation name that was never declared or registered
+    UnknownRelation(String),
+    /// No degree sequence was registered for a relation's attribute
+    MissingStatistics { relation: String, attribute: String },
+    /// The query shape is not handled by the estimator
+    UnsupportedQuery(String),
+}
+
+impl fmt::Display for LpBoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LpBoundError::UnknownRelation(name) => write!(f, "unknown relation: {}", name),
+            LpBoundError::MissingStatistics { relation, attribute } => {
+                write!(f, "no statistics for {}.{}", relation, attribute)
+            }
+            LpBoundError::UnsupportedQuery(reason) => write!(f, "unsupported query: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LpBoundError {}
+
+/// Fluent builder for a `JoinQuery`
+#[derive(Debug, Default)]
+pub struct JoinQueryBuilder {
+    relations: Vec<String>,
+    join_conditions: Vec<(String, String, String, String)>,
+    group_by: Vec<(String, String)>,
+}
+
+impl JoinQueryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn relation(mut self, name: &str) -> Self {
+        self.relations.push(name.to_string());
+        self
+    }
+
+    pub fn join(mut self, rel1: &str, attr1: &str, rel2: &str, attr2: &str) -> Self {
+        self.join_conditions.push((rel1.to_string(), attr1.to_string(), rel2.to_string(), attr2.to_string()));
+        self
+    }
+
+    pub fn group_by(mut self, rel: &str, attr: &str) -> Self {
+        self.group_by.push((rel.to_string(), attr.to_string()));
+        self
+    }
+
+    /// Build the query, rejecting conditions on relations that were not declared
+    pub fn build(self) -> Result<JoinQuery, LpBoundError> {
+        let referenced = self.join_conditions.iter()
+            .flat_map(|(rel1, _, rel2, _)| [rel1, rel2])
+            .chain(self.group_by.iter().map(|(rel, _)| rel));
+        for rel in referenced {
+            if !self.relations.contains(rel) {
+                return Err(LpBoundError::UnknownRelation(rel.clone()));
+            }
+        }
+
+        Ok(JoinQuery {
+            relations: self.relations,
+            join_conditions: self.join_conditions,
+            group_by: self.group_by,
+        })
+    }
+}
+
+/// The q-inequalities used to bound a two-way join `R ⋊⋉ S`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundKind {
+    /// |R| · |S|
+    Agm,
+    /// |R| · ||deg_S(Y)||_∞
+    LeftCardinality,
+    /// ||deg_R(X)||_∞ · |S|
+    RightCardinality,
+    /// ||deg_R(X)||_2 · ||deg_S(Y)||_2
+    L2,
+}
+
+/// All bounds computed for a two-way join, with the tightest one singled out
+#[derive(Debug)]
+pub struct EstimateBreakdown {
+    pub bounds: Vec<(BoundKind, f64)>,
+    pub tightest: BoundKind,
+    /// Tightest bound divided by the loosest one; small values mean the best statistic helped a lot
+    pub tightness_ratio: f64,
+}
+
+impl EstimateBreakdown {
+    fn new(bounds: Vec<(BoundKind, f64)>) -> Self {
+        let (tightest, min) = bounds.iter().cloned()
+            .fold((BoundKind::Agm, f64::INFINITY), |acc, b| if b.1 < acc.1 { b } else { acc });
+        let max = bounds.iter().map(|&(_, b)| b).fold(0.0, f64::max);
+        // When every bound is 0 they are all equally tight
+        let tightness_ratio = if max > 0.0 { min / max } else { 1.0 };
+
+        Self { bounds, tightest, tightness_ratio }
+    }
+
+    /// The estimate itself, i.e. the tightest bound
+    pub fn estimate(&self) -> f64 {
+        self.bounds.iter().map(|&(_, b)| b).fold(f64::INFINITY, f64::min)
+    }
+}
+
 /// LpBound cardinality estimator
 pub struct LpBound {
     relations: HashMap<String, Relation>,
@@ -115,32 +706,172 @@ impl LpBound {
 
     /// Simplified estimation for a two-way join
     pub fn estimate_two_way_join(&self, query: &JoinQuery) -> f64 {
+        self.estimate_with_breakdown(query).estimate()
+    }
+
+    /// Compute every two-way join bound and report which one is tightest
+    pub fn estimate_with_breakdown(&self, query: &JoinQuery) -> EstimateBreakdown {
         if query.relations.len() != 2 || query.join_conditions.len() != 1 {
             panic!("This simplified implementation only handles two-way joins with one join condition");
         }
 
-        let join_condition = &query.join_conditions[0];
+        self.condition_breakdown(&query.join_conditions[0])
+    }
+
+    /// Compute the two-way join bounds for a single join condition of the query
+    fn condition_breakdown(&self, join_condition: &(String, String, String, String)) -> EstimateBreakdown {
         let (rel1, attr1, rel2, attr2) = join_condition;
 
+        // A self-join names the same relation on both sides, so both lookups return it
         let r1 = self.relations.get(rel1).unwrap();
         let r2 = self.relations.get(rel2).unwrap();
 
+        // NULL keys never match, so only the non-NULL tuples of each side can join
+        let card1 = r1.get_lp_norm(attr1, 1).unwrap() * r1.non_null_fraction(attr1);
+        let card2 = r2.get_lp_norm(attr2, 1).unwrap() * r2.non_null_fraction(attr2);
+
         // Calculate different bounds based on q-inequalities from the paper
+        let bounds = vec![
+            // |R ⋊⋉ S| ≤ |R| · |S|
+            (BoundKind::Agm, card1 * card2),
+            // |R ⋊⋉ S| ≤ |R| · ||deg_S(Y)||_∞
+            (BoundKind::LeftCardinality, card1 * r2.get_lp_norm(attr2, 0).unwrap()),
+            // |R ⋊⋉ S| ≤ ||deg_R(X)||_∞ · |S|
+            (BoundKind::RightCardinality, r1.get_lp_norm(attr1, 0).unwrap() * card2),
+            // |R ⋊⋉ S| ≤ ||deg_R(X)||_2 · ||deg_S(Y)||_2
+            (BoundKind::L2, r1.get_lp_norm(attr1, 2).unwrap() * r2.get_lp_norm(attr2, 2).unwrap()),
+        ];
+
+        EstimateBreakdown::new(bounds)
+    }
+
+    /// Skew-aware bound for a two-way join: split both join keys into heavy hitters (degree above
+    /// `threshold`) and light values, bound each of the four heavy/light pairings separately and
+    /// sum them. A single extreme value then only inflates the pairings it belongs to. Both this sum
+    /// and the unpartitioned bound are valid, so the smaller one is returned.
+    pub fn estimate_skew_aware(&self, query: &JoinQuery, threshold: usize) -> f64 {
+        if query.relations.len() != 2 || query.join_conditions.len() != 1 {
+            panic!("This simplified implementation only handles two-way joins with one join condition");
+        }
+
+        let (rel1, attr1, rel2, attr2) = &query.join_conditions[0];
+        let r1 = self.relations.get(rel1).unwrap();
+        let r2 = self.relations.get(rel2).unwrap();
+        let (seq1, seq2) = (&r1.degree_sequences[attr1], &r2.degree_sequences[attr2]);
+        let (heavy1, light1) = seq1.split_heavy_light(threshold);
+        let (heavy2, light2) = seq2.split_heavy_light(threshold);
 
-        // |R ⋊⋉ S| ≤ |R| · |S|
-        let agm_bound = r1.get_lp_norm(attr1, 1).unwrap() * r2.get_lp_norm(attr2, 1).unwrap();
+        // Which partition the NULLs fall into is unknown, so each partition can only be capped at
+        // the relation's total number of non-NULL tuples
+        let non_null1 = seq1.cardinality() as f64 * r1.non_null_fraction(attr1);
+        let non_null2 = seq2.cardinality() as f64 * r2.non_null_fraction(attr2);
+
+        let bound: f64 = [(&heavy1, &heavy2), (&heavy1, &light2), (&light1, &heavy2), (&light1, &light2)]
+            .iter()
+            .map(|(a, b)| sequence_join_bound(a, b, non_null1, non_null2))
+            .sum();
+
+        bound.min(self.estimate_two_way_join(query))
+    }
+
+    /// Estimate for a two-way join on several attribute pairs, e.g. `R.a = S.a AND R.b = S.b`.
+    ///
+    /// Each condition alone bounds the join, so the smallest single-condition bound is a
+    /// guaranteed upper bound. Assuming the conditions filter independently gives a smaller
+    /// estimate, `|R| · |S|` times the product of the per-condition selectivities. Positively
+    /// correlated join attributes filter less than that, so the estimate is moved from the
+    /// independence estimate toward the upper bound by the strongest correlation `ρ` found,
+    /// reaching the bound at `ρ = 1`. The result never exceeds the upper bound.
+    pub fn estimate_multi_attribute_join(&self, query: &JoinQuery) -> f64 {
+        if query.relations.len() != 2 || query.join_conditions.is_empty() {
+            panic!("This simplified implementation only handles two-way joins");
+        }
+
+        let (left, right) = (&query.relations[0], &query.relations[1]);
+        let cardinality = |rel: &String| self.relations[rel].cardinality().unwrap_or(0) as f64;
+        let cross = cardinality(left) * cardinality(right);
+        let mut bound = f64::INFINITY;
+        let mut independent = cross;
+        for condition in &query.join_conditions {
+            let breakdown = self.condition_breakdown(condition);
+            let agm = breakdown.bounds.iter()
+                .find(|(kind, _)| *kind == BoundKind::Agm)
+                .map_or(0.0, |&(_, b)| b);
+            let estimate = breakdown.estimate();
+            bound = bound.min(estimate);
+            independent *= if agm > 0.0 { estimate / agm } else { 0.0 };
+        }
+        let independent = independent.min(bound);
+
+        // Orient every condition as (attribute of the first relation, attribute of the second), so
+        // `R.a = S.a AND S.b = R.b` pairs R.a with R.b
+        let oriented: Vec<(&String, &String)> = query.join_conditions.iter()
+            .map(|(rel1, attr1, _, attr2)| if rel1 == left { (attr1, attr2) } else { (attr2, attr1) })
+            .collect();
+
+        // Strongest positive correlation between two join attributes of the same relation
+        let mut correlation: f64 = 0.0;
+        for (i, (left_attr, right_attr)) in oriented.iter().enumerate() {
+            for (other_left, other_right) in &oriented[i + 1..] {
+                correlation = correlation
+                    .max(self.relations[left].correlation(left_attr, other_left))
+                    .max(self.relations[right].correlation(right_attr, other_right));
+            }
+        }
+
+        independent + correlation.min(1.0) * (bound - independent)
+    }
+
+    /// Bound for `R ⟕ S`: the matched pairs plus at most one padded row per unmatched tuple of R
+    pub fn estimate_left_outer_join(&self, query: &JoinQuery) -> Result<f64, LpBoundError> {
+        let (card1, _) = self.join_input_cardinalities(query)?;
+        Ok(self.estimate_two_way_join(query) + card1)
+    }
+
+    /// Bound for `R ⟗ S`: the matched pairs plus at most one padded row per unmatched tuple of R or S
+    pub fn estimate_full_outer_join(&self, query: &JoinQuery) -> Result<f64, LpBoundError> {
+        let (card1, card2) = self.join_input_cardinalities(query)?;
+        Ok(self.estimate_two_way_join(query) + card1 + card2)
+    }
+
+    /// Bound for `R UNION S`: at most |R| + |S| tuples, and at most NDV(R) + NDV(S) distinct ones
+    pub fn estimate_union(&self, rel1: &str, rel2: &str) -> Result<f64, LpBoundError> {
+        let (r1, r2) = (self.relation(rel1)?, self.relation(rel2)?);
+        let missing = |rel: &Relation| LpBoundError::MissingStatistics {
+            relation: rel.name.clone(),
+            attribute: "*".to_string(),
+        };
+
+        let union_all_bound = (r1.cardinality().ok_or_else(|| missing(r1))?
+            + r2.cardinality().ok_or_else(|| missing(r2))?) as f64;
+        let ndv_bound = (r1.ndv().ok_or_else(|| missing(r1))? + r2.ndv().ok_or_else(|| missing(r2))?) as f64;
+
+        Ok(union_all_bound.min(ndv_bound))
+    }
 
-        // |R ⋊⋉ S| ≤ |R| · ||deg_S(Y)||_∞
-        let bound1 = r1.get_lp_norm(attr1, 1).unwrap() * r2.get_lp_norm(attr2, 0).unwrap();
+    /// Look up a registered relation by name
+    fn relation(&self, name: &str) -> Result<&Relation, LpBoundError> {
+        self.relations.get(name).ok_or_else(|| LpBoundError::UnknownRelation(name.to_string()))
+    }
 
-        // |R ⋊⋉ S| ≤ ||deg_R(X)||_∞ · |S|
-        let bound2 = r1.get_lp_norm(attr1, 0).unwrap() * r2.get_lp_norm(attr2, 1).unwrap();
+    /// Look up the ℓp-norm of a relation's attribute
+    fn lp_norm(&self, rel: &str, attr: &str, p: usize) -> Result<f64, LpBoundError> {
+        self.relation(rel)?.get_lp_norm(attr, p).ok_or_else(|| LpBoundError::MissingStatistics {
+            relation: rel.to_string(),
+            attribute: attr.to_string(),
+        })
+    }
 
-        // |R ⋊⋉ S| ≤ ||deg_R(X)||_2 · ||deg_S(Y)||_2
-        let bound3 = r1.get_lp_norm(attr1, 2).unwrap() * r2.get_lp_norm(attr2, 2).unwrap();
+    /// Check that the query is a two-way join and return the cardinalities of both inputs
+    fn join_input_cardinalities(&self, query: &JoinQuery) -> Result<(f64, f64), LpBoundError> {
+        if query.relations.len() != 2 || query.join_conditions.len() != 1 {
+            return Err(LpBoundError::UnsupportedQuery(
+                "only two-way joins with one join condition are handled".to_string(),
+            ));
+        }
 
-        // Return the minimum (tightest) bound
-        [agm_bound, bound1, bound2, bound3].iter().cloned().fold(f64::INFINITY, f64::min)
+        let (rel1, attr1, rel2, attr2) = &query.join_conditions[0];
+        Ok((self.lp_norm(rel1, attr1, 1)?, self.lp_norm(rel2, attr2, 1)?))
     }
 
     /// Just showing the concept - in reality we would use an LP solver
@@ -160,6 +891,99 @@ impl LpBound {
     }
 }
 
+/// Skew threshold used by the statistics report: p99 degree over median degree
+const REPORT_SKEW_THRESHOLD: f64 = 10.0;
+
+/// Statistics of one attribute in a `StatisticsReport`
+#[derive(Debug)]
+pub struct AttributeReport {
+    pub relation: String,
+    pub attribute: String,
+    pub cardinality: usize,
+    pub max_degree: usize,
+    pub l2_norm: f64,
+    pub ndv: usize,
+    pub skewed: bool,
+}
+
+/// Per-relation, per-attribute summary of everything registered in an `LpBound`
+#[derive(Debug)]
+pub struct StatisticsReport {
+    pub attributes: Vec<AttributeReport>,
+}
+
+/// Collect a report over all relations, sorted by relation name and in attribute order
+pub fn generate_statistics_report(lpbound: &LpBound) -> StatisticsReport {
+    let mut names: Vec<&String> = lpbound.relations.keys().collect();
+    names.sort();
+
+    let mut attributes = Vec::new();
+    for name in names {
+        let relation = &lpbound.relations[name];
+        for attr in &relation.attributes {
+            if let Some(seq) = relation.degree_sequences.get(attr) {
+                attributes.push(AttributeReport {
+                    relation: name.clone(),
+                    attribute: attr.clone(),
+                    cardinality: seq.cardinality(),
+                    max_degree: seq.max_degree(),
+                    l2_norm: seq.lp_norm(2.0),
+                    ndv: seq.ndv(),
+                    skewed: seq.is_skewed(REPORT_SKEW_THRESHOLD),
+                });
+            }
+        }
+    }
+
+    StatisticsReport { attributes }
+}
+
+/// Escape a string for use inside a JSON string literal
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl StatisticsReport {
+    /// Render the report as a JSON array of attribute objects
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self.attributes.iter().map(|a| format!(
+            "{{\"relation\":\"{}\",\"attribute\":\"{}\",\"cardinality\":{},\"max_degree\":{},\"l2_norm\":{},\"ndv\":{},\"skewed\":{}}}",
+            json_escape(&a.relation), json_escape(&a.attribute), a.cardinality, a.max_degree, a.l2_norm, a.ndv, a.skewed
+        )).collect();
+        format!("[{}]", rows.join(","))
+    }
+}
+
+impl fmt::Display for StatisticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {:<12} {:>12} {:>10} {:>10} {:>8} {:>6}",
+            "relation", "attribute", "cardinality", "max_degree", "l2_norm", "ndv", "skewed"
+        )?;
+        for a in &self.attributes {
+            write!(
+                f,
+                "\n{:<12} {:<12} {:>12} {:>10} {:>10.2} {:>8} {:>6}",
+                a.relation, a.attribute, a.cardinality, a.max_degree, a.l2_norm, a.ndv, a.skewed
+            )?;
+        }
+        Ok(())
+    }
+}
+
 fn main() {
     // Example usage
     let mut lpbound = LpBound::new();
@@ -169,12 +993,17 @@ fn main() {
 
     // Create a sample degree sequence for R.X
     let seq_x = DegreeSequence { degrees: vec![3, 2, 2, 1] };
+    println!("{}", seq_x);
     r.add_degree_sequence("X", seq_x);
 
     // Create a sample degree sequence for R.Y
     let seq_y = DegreeSequence { degrees: vec![4, 3, 1] };
     r.add_degree_sequence("Y", seq_y);
 
+    // R.X and R.Y are perfectly correlated
+    r.add_correlation(CorrelationStats { col1: "X".to_string(), col2: "Y".to_string(), correlation: 1.0 });
+
+    println!("{}", r);
     lpbound.add_relation(r);
 
     // Create relation S(Y, Z)
@@ -182,24 +1011,499 @@ fn main() {
 
     // Create a sample degree sequence for S.Y
     let seq_y = DegreeSequence { degrees: vec![3, 2, 1, 1, 1] };
+    println!("S.Y median degree: {}, p99 degree: {}, skewed: {}", seq_y.median(), seq_y.percentile(0.99), seq_y.is_skewed(2.0));
     s.add_degree_sequence("Y", seq_y);
 
     // Create a sample degree sequence for S.Z
     let seq_z = DegreeSequence { degrees: vec![5, 2, 1] };
+    println!("|σ(S.Z)| with selectivity 0.5 = {}", seq_z.apply_selectivity(0.5).cardinality());
+    let compressed = serialize_compressed(&seq_z);
+    println!(
+        "S.Z compressed to {} bytes (from {}), round-trips: {}, ℓ2-norm: {}",
+        compressed.len(),
+        seq_z.degrees.len() * std::mem::size_of::<usize>(),
+        deserialize_compressed(&compressed).unwrap().degrees == seq_z.degrees,
+        lp_norm_compressed(&compressed, 2.0).unwrap()
+    );
     s.add_degree_sequence("Z", seq_z);
 
     lpbound.add_relation(s);
 
+    // Union the two relations on their shared attribute Y
+    let r_union_s = lpbound.relations["R"].union_all("R_UNION_S", &lpbound.relations["S"]);
+    println!("|R UNION ALL S| = {}", r_union_s.get_lp_norm("Y", 1).unwrap());
+
+    let report = generate_statistics_report(&lpbound);
+    println!("{}", report);
+    println!("{}", report.to_json());
+
     // Create a two-way join query
-    let query = JoinQuery {
-        relations: vec!["R".to_string(), "S".to_string()],
-        join_conditions: vec![
-            ("R".to_string(), "Y".to_string(), "S".to_string(), "Y".to_string())
-        ],
-        group_by: vec![],
-    };
+    let query = JoinQueryBuilder::new()
+        .relation("R")
+        .relation("S")
+        .join("R", "Y", "S", "Y")
+        .build()
+        .unwrap();
 
     // Estimate the cardinality
     let estimate = lpbound.estimate(&query);
     println!("Estimated upper bound: {}", estimate);
+
+    let breakdown = lpbound.estimate_with_breakdown(&query);
+    println!("Tightest bound: {:?} (ratio to loosest: {:.3})", breakdown.tightest, breakdown.tightness_ratio);
+    println!("Left outer join bound: {}", lpbound.estimate_left_outer_join(&query).unwrap());
+    println!("Full outer join bound: {}", lpbound.estimate_full_outer_join(&query).unwrap());
+    println!("Union bound: {}", lpbound.estimate_union("R", "S").unwrap());
+
+    // Self-join R r1 JOIN R r2 ON r1.X = r2.Y
+    let self_join = JoinQueryBuilder::new()
+        .relation("R")
+        .relation("R")
+        .join("R", "X", "R", "Y")
+        .build()
+        .unwrap();
+    println!("Self-join bound: {}", lpbound.estimate(&self_join));
+
+    // Multi-attribute join R.Y = S.Y AND R.X = S.Z over the correlated attributes of R
+    let multi = JoinQueryBuilder::new()
+        .relation("R")
+        .relation("S")
+        .join("R", "Y", "S", "Y")
+        .join("R", "X", "S", "Z")
+        .build()
+        .unwrap();
+    println!("Multi-attribute join estimate: {}", lpbound.estimate_multi_attribute_join(&multi));
+    println!("Skew-aware bound (threshold 2): {}", lpbound.estimate_skew_aware(&query, 2));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relation(name: &str, attr: &str, degrees: Vec<usize>) -> Relation {
+        let mut relation = Relation::new(name, vec![attr]);
+        relation.add_degree_sequence(attr, DegreeSequence { degrees });
+        relation
+    }
+
+    fn lpbound(relations: Vec<Relation>) -> LpBound {
+        let mut lpbound = LpBound::new();
+        for relation in relations {
+            lpbound.add_relation(relation);
+        }
+        lpbound
+    }
+
+    fn join(rel1: &str, attr1: &str, rel2: &str, attr2: &str) -> JoinQuery {
+        JoinQueryBuilder::new().relation(rel1).relation(rel2).join(rel1, attr1, rel2, attr2).build().unwrap()
+    }
+
+    #[test]
+    fn half_null_column_halves_the_bound() {
+        let query = join("R", "Y", "S", "Y");
+        // The tightest bound is ||deg_R||_∞ · |S| = 20
+        let without_nulls = lpbound(vec![relation("R", "Y", vec![1, 1, 1, 1]), relation("S", "Y", vec![10, 10])]);
+
+        let mut s = relation("S", "Y", vec![10, 10]);
+        s.add_null_fraction("Y", 0.5);
+        let with_nulls = lpbound(vec![relation("R", "Y", vec![1, 1, 1, 1]), s]);
+
+        assert_eq!(without_nulls.estimate_two_way_join(&query), 20.0);
+        assert_eq!(with_nulls.estimate_two_way_join(&query), 10.0);
+    }
+
+    #[test]
+    fn null_fraction_only_scales_cardinalities() {
+        // S.Y = {NULL: 50, v: 50}, R.Y = {v: 1}: the true join has 50 rows
+        let mut s = relation("S", "Y", vec![50, 50]);
+        s.add_null_fraction("Y", 0.5);
+        let lpbound = lpbound(vec![relation("R", "Y", vec![1]), s]);
+        let query = join("R", "Y", "S", "Y");
+
+        assert!(lpbound.estimate_two_way_join(&query) >= 50.0);
+        assert!(lpbound.estimate_skew_aware(&query, 10) >= 50.0);
+    }
+
+    #[test]
+    fn selectivity_skips_nulls() {
+        let mut r = relation("R", "Y", vec![40, 40, 20]);
+        r.add_null_fraction("Y", 0.5);
+        assert_eq!(r.apply_selectivity("Y", 0.5).unwrap().cardinality(), 25);
+        assert!(r.apply_selectivity("Z", 0.5).is_none());
+    }
+
+    #[test]
+    fn outer_join_bounds_cover_unmatched_tuples() {
+        // R.Y = {a: 1, b: 1}, S.Y = {a: 100}: R ⟕ S has 101 rows, R ⟗ S has 101 as well
+        let lpbound = lpbound(vec![relation("R", "Y", vec![1, 1]), relation("S", "Y", vec![100])]);
+        let query = join("R", "Y", "S", "Y");
+
+        assert!(lpbound.estimate_left_outer_join(&query).unwrap() >= 101.0);
+        assert!(lpbound.estimate_full_outer_join(&query).unwrap() >= 101.0);
+        assert_eq!(
+            lpbound.estimate_full_outer_join(&query).unwrap(),
+            lpbound.estimate_left_outer_join(&query).unwrap() + 100.0
+        );
+    }
+
+    #[test]
+    fn self_join_uses_one_relation_for_both_sides() {
+        let mut r = Relation::new("R", vec!["X", "Y"]);
+        r.add_degree_sequence("X", DegreeSequence { degrees: vec![3, 2, 2, 1] });
+        r.add_degree_sequence("Y", DegreeSequence { degrees: vec![4, 3, 1] });
+        let lpbound = lpbound(vec![r]);
+
+        let query = join("R", "X", "R", "Y");
+        assert!(query.is_self_join());
+        let bound = lpbound.estimate(&query);
+        assert!(bound.is_finite());
+        // Tighter than ||deg_R(X)||_∞ · |R| = 3 · 8 thanks to the ℓ2 bound
+        assert!(bound <= 24.0);
+    }
+
+    // Orders(customer, region) referencing Customers(id, region), where customer and region of an
+    // order are perfectly correlated
+    fn foreign_key_join(correlation: f64) -> LpBound {
+        let mut orders = Relation::new("Orders", vec!["customer", "region"]);
+        orders.add_degree_sequence("customer", DegreeSequence { degrees: vec![5, 3, 2] });
+        orders.add_degree_sequence("region", DegreeSequence { degrees: vec![8, 2] });
+        orders.add_correlation(CorrelationStats {
+            col1: "customer".to_string(),
+            col2: "region".to_string(),
+            correlation,
+        });
+        let mut customers = Relation::new("Customers", vec!["id", "region"]);
+        customers.add_degree_sequence("id", DegreeSequence { degrees: vec![1, 1, 1] });
+        customers.add_degree_sequence("region", DegreeSequence { degrees: vec![2, 1] });
+        lpbound(vec![orders, customers])
+    }
+
+    #[test]
+    fn correlation_raises_multi_attribute_estimate_to_the_bound() {
+        let query = JoinQueryBuilder::new()
+            .relation("Orders")
+            .relation("Customers")
+            .join("Orders", "customer", "Customers", "id")
+            .join("Orders", "region", "Customers", "region")
+            .build()
+            .unwrap();
+        let independent = foreign_key_join(0.0).estimate_multi_attribute_join(&query);
+        let partial = foreign_key_join(0.5).estimate_multi_attribute_join(&query);
+        let correlated = foreign_key_join(1.0).estimate_multi_attribute_join(&query);
+        // Every order matches its customer and, with matching regions, the region condition too,
+        // so the true join has one row per order
+        assert!(correlated >= 10.0);
+        let customer_bound = foreign_key_join(1.0).estimate_two_way_join(&join("Orders", "customer", "Customers", "id"));
+        assert_eq!(correlated, customer_bound);
+        assert!(independent < partial && partial < correlated);
+    }
+
+    #[test]
+    fn correlation_found_for_conditions_in_either_orientation() {
+        let query = JoinQueryBuilder::new()
+            .relation("Orders")
+            .relation("Customers")
+            .join("Orders", "customer", "Customers", "id")
+            .join("Customers", "region", "Orders", "region")
+            .build()
+            .unwrap();
+        let independent = foreign_key_join(0.0).estimate_multi_attribute_join(&query);
+        let correlated = foreign_key_join(1.0).estimate_multi_attribute_join(&query);
+        assert!(correlated >= 10.0);
+        assert!(independent < correlated);
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let data: Vec<u32> = (0..20_000).map(|i| if i % 3 == 0 { i % 100 } else { i }).collect();
+        let seq = DegreeSequence::from_data(&data);
+        let compressed = serialize_compressed(&seq);
+
+        assert_eq!(deserialize_compressed(&compressed).unwrap().degrees, seq.degrees);
+        assert!(compressed.len() < seq.degrees.len());
+        for p in [1.0, 2.0, 3.0, f64::INFINITY] {
+            let expected = seq.lp_norm(p);
+            assert!((lp_norm_compressed(&compressed, p).unwrap() - expected).abs() <= 1e-9 * expected);
+        }
+        assert_eq!(deserialize_compressed(&[]).unwrap().degrees, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn corrupt_compressed_input_is_rejected() {
+        // Degree rises from 1 to 6
+        assert_eq!(deserialize_compressed(&[1, 1, 5, 1]).unwrap_err(), CorruptSequence);
+        // Varint longer than 64 bits
+        assert!(deserialize_compressed(&[0xff; 12]).is_err());
+        // Second run has no count
+        assert!(deserialize_compressed(&[3, 2, 1]).is_err());
+        // Empty run
+        assert!(deserialize_compressed(&[3, 0]).is_err());
+        assert!(lp_norm_compressed(&[3, 2, 1], 2.0).is_err());
+    }
+
+    #[test]
+    fn parallel_lp_norm_matches_sequential() {
+        let large = DegreeSequence { degrees: (1..=1_000_000).rev().collect() };
+        for p in [1.0, 2.0, 3.0, f64::INFINITY] {
+            let sequential = large.lp_norm(p);
+            assert!((large.lp_norm_parallel(p) - sequential).abs() <= 1e-9 * sequential);
+        }
+    }
+
+    // Run with `--ignored --nocapture` to compare the two paths
+    #[test]
+    #[ignore]
+    fn parallel_lp_norm_timing() {
+        let large = DegreeSequence { degrees: (1..=1_000_000).rev().collect() };
+        let start = std::time::Instant::now();
+        let sequential = large.lp_norm(2.0);
+        let sequential_time = start.elapsed();
+        let start = std::time::Instant::now();
+        let parallel = large.lp_norm_parallel(2.0);
+        println!(
+            "ℓ2-norm of 1M degrees: {:.0} sequential in {:?}, {:.0} parallel in {:?}",
+            sequential, sequential_time, parallel, start.elapsed()
+        );
+    }
+
+    #[test]
+    fn union_all_of_identical_sequences_doubles_each_rank() {
+        let seq = DegreeSequence { degrees: vec![5, 3, 3, 1] };
+        assert_eq!(merge_degree_sequences_union_all(&seq, &seq).degrees, vec![10, 6, 6, 2]);
+
+        let shorter = DegreeSequence { degrees: vec![4] };
+        assert_eq!(merge_degree_sequences_union_all(&seq, &shorter).degrees, vec![9, 3, 3, 1]);
+
+        let union = relation("R", "Y", vec![5, 3, 3, 1]).union_all("R_UNION_R", &relation("R", "Y", vec![5, 3, 3, 1]));
+        assert_eq!(union.get_lp_norm("Y", 1), Some(24.0));
+        assert_eq!(union.get_lp_norm("Y", 0), Some(10.0));
+    }
+
+    #[test]
+    fn selectivity_scales_and_shrinks_norms() {
+        let seq = DegreeSequence { degrees: (1..=1000).rev().collect() };
+
+        // Every degree is scaled by sel, so |σ(R)| ≈ sel · |R|. The other ℓp-norms shrink by
+        // about sel too, which is at most the sel^(1/p) of dropping whole values instead
+        let half = seq.apply_selectivity(0.5);
+        assert!((half.cardinality() as f64 / seq.cardinality() as f64 - 0.5).abs() < 0.01);
+        for p in [2.0, 3.0, f64::INFINITY] {
+            let ratio = half.lp_norm(p) / seq.lp_norm(p);
+            assert!((ratio - 0.5).abs() < 0.01, "ℓ{} ratio {}", p, ratio);
+            assert!(ratio <= 0.5f64.powf(1.0 / p));
+        }
+
+        // Rounding drops values whose degree falls below 1/2, and keeps the sequence sorted
+        let small = DegreeSequence { degrees: vec![10, 3, 1] }.apply_selectivity(0.2);
+        assert_eq!(small.degrees, vec![2, 1]);
+
+        let sels = [1.0, 0.8, 0.5, 0.2, 0.05, 0.0];
+        for p in [1.0, 2.0, f64::INFINITY] {
+            let norms: Vec<f64> = sels.iter().map(|&sel| seq.apply_selectivity(sel).lp_norm(p)).collect();
+            assert!(norms.windows(2).all(|w| w[0] >= w[1]), "ℓ{} norms {:?}", p, norms);
+        }
+        assert_eq!(seq.apply_selectivity(1.0).degrees, seq.degrees);
+        assert_eq!(seq.apply_selectivity(0.0).cardinality(), 0);
+    }
+
+    #[test]
+    fn display_renders_degree_histogram() {
+        let seq = DegreeSequence { degrees: vec![3, 2, 2, 1] };
+        assert_eq!(
+            format!("{}", seq),
+            "degree 3: 1 value (37.5%)\ndegree 2: 2 values (50.0%)\ndegree 1: 1 value (12.5%)"
+        );
+        assert_eq!(format!("{}", DegreeSequence { degrees: vec![4, 4] }), "degree 4: 2 values (100.0%)");
+        assert_eq!(format!("{}", DegreeSequence { degrees: vec![] }), "");
+
+        let r = relation("R", "Y", vec![4, 3, 1]);
+        assert_eq!(
+            format!("{}", r),
+            "R(Y)\n  Y: ℓ1=8.00 ℓ2=5.10 ℓ3=4.51 ℓ4=4.29 ℓ∞=4.00"
+        );
+    }
+
+    #[test]
+    fn builder_matches_struct_literal() {
+        let built = JoinQueryBuilder::new()
+            .relation("R")
+            .relation("S")
+            .join("R", "Y", "S", "Y")
+            .group_by("R", "X")
+            .build()
+            .unwrap();
+        let literal = JoinQuery {
+            relations: vec!["R".to_string(), "S".to_string()],
+            join_conditions: vec![("R".to_string(), "Y".to_string(), "S".to_string(), "Y".to_string())],
+            group_by: vec![("R".to_string(), "X".to_string())],
+        };
+        assert_eq!(built, literal);
+    }
+
+    #[test]
+    fn builder_rejects_undeclared_relations() {
+        let unknown = |rel: &str| Err(LpBoundError::UnknownRelation(rel.to_string()));
+        assert_eq!(JoinQueryBuilder::new().relation("R").join("R", "Y", "T", "Y").build(), unknown("T"));
+        assert_eq!(JoinQueryBuilder::new().relation("R").group_by("U", "X").build(), unknown("U"));
+    }
+
+    #[test]
+    fn breakdown_of_sample_query_picks_l2_bound() {
+        // The sample query from main: R.Y = [4, 3, 1] joined with S.Y = [3, 2, 1, 1, 1]
+        let lpbound = lpbound(vec![relation("R", "Y", vec![4, 3, 1]), relation("S", "Y", vec![3, 2, 1, 1, 1])]);
+        let breakdown = lpbound.estimate_with_breakdown(&join("R", "Y", "S", "Y"));
+
+        let l2 = 26f64.sqrt() * 4.0;
+        assert_eq!(
+            breakdown.bounds,
+            vec![
+                (BoundKind::Agm, 64.0),
+                (BoundKind::LeftCardinality, 24.0),
+                (BoundKind::RightCardinality, 32.0),
+                (BoundKind::L2, l2),
+            ]
+        );
+        assert_eq!(breakdown.tightest, BoundKind::L2);
+        assert_eq!(breakdown.estimate(), l2);
+        assert!((breakdown.tightness_ratio - l2 / 64.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn percentiles_of_known_sequences() {
+        let uniform = DegreeSequence { degrees: (1..=100).rev().collect() };
+        assert_eq!(uniform.percentile(0.0), 1);
+        assert_eq!(uniform.percentile(0.25), 25);
+        assert_eq!(uniform.median(), 50);
+        assert_eq!(uniform.percentile(0.99), 99);
+        assert_eq!(uniform.percentile(1.0), 100);
+        assert!(!uniform.is_skewed(2.0));
+        assert!(uniform.is_skewed(1.5));
+
+        // With 50 values the 99th percentile is the single heavy hitter
+        let mut degrees = vec![1000];
+        degrees.extend(std::iter::repeat_n(1, 49));
+        let skewed = DegreeSequence { degrees };
+        assert_eq!(skewed.median(), 1);
+        assert_eq!(skewed.percentile(0.99), 1000);
+        assert!(skewed.is_skewed(10.0));
+
+        let empty = DegreeSequence { degrees: vec![] };
+        assert_eq!(empty.percentile(0.99), 0);
+        assert!(!empty.is_skewed(0.0));
+    }
+
+    // Write `content` to a CSV file in the temp dir that is unique to this test process
+    fn temp_csv(stem: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}_{}.csv", stem, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn csv_statistics_keep_frequency_sums() {
+        let path = temp_csv(
+            "orders",
+            "attr_name,value,frequency\nX,a,5\nX,b,3\nY,de,7\n\nX,c,2\nY,fr,1\nX,a,1\n",
+        );
+        let relation = load_relation_from_csv(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(relation.name.starts_with("orders_"));
+        assert_eq!(relation.attributes, vec!["X", "Y"]);
+        assert_eq!(relation.get_lp_norm("X", 1), Some(11.0));
+        assert_eq!(relation.get_lp_norm("Y", 1), Some(8.0));
+        // Repeated rows for a value add up
+        assert_eq!(relation.degree_sequences["X"].degrees, vec![6, 3, 2]);
+    }
+
+    #[test]
+    fn csv_statistics_errors() {
+        assert!(matches!(load_relation_from_csv("/nonexistent/stats.csv"), Err(StatsError::Io(_))));
+
+        let path = temp_csv("no_frequency", "attr_name,value\nX,a\n");
+        let missing = load_relation_from_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(missing, Err(StatsError::MissingColumn(column)) if column == "frequency"));
+
+        let path = temp_csv("bad_frequency", "attr_name,value,frequency\nX,a,1\nX,b,many\n");
+        let bad = load_relation_from_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(bad, Err(StatsError::Parse { line: 3, .. })));
+    }
+
+    #[test]
+    fn skew_aware_bound_isolates_an_outlier() {
+        // One super-node of degree 1000 among 999 values of degree 1, on both sides
+        let mut degrees = vec![1000];
+        degrees.extend(std::iter::repeat_n(1, 999));
+        let lpbound = lpbound(vec![relation("R", "Y", degrees.clone()), relation("S", "Y", degrees)]);
+        let query = join("R", "Y", "S", "Y");
+
+        // ||deg_R||_∞ · |S| = 1000 · 1999, while the true join has 1000² + 999 rows
+        let breakdown = lpbound.estimate_with_breakdown(&query);
+        let linf_bound = breakdown.bounds.iter()
+            .filter(|(kind, _)| matches!(kind, BoundKind::LeftCardinality | BoundKind::RightCardinality))
+            .map(|&(_, b)| b)
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!(linf_bound, 1_999_000.0);
+
+        let skew_aware = lpbound.estimate_skew_aware(&query, 10);
+        assert!(skew_aware >= 1_000_999.0);
+        assert!(skew_aware < 0.6 * linf_bound, "skew-aware bound {}", skew_aware);
+    }
+
+    #[test]
+    fn cardinality_is_largest_attribute_total() {
+        let mut r = Relation::new("R", vec!["X", "Y"]);
+        r.add_degree_sequence("X", DegreeSequence { degrees: vec![6, 3, 2] });
+        r.add_degree_sequence("Y", DegreeSequence { degrees: vec![1; 8] });
+        assert_eq!(r.cardinality(), Some(11));
+        // 3 · 8 distinct pairs at most, capped by the 11 tuples
+        assert_eq!(r.ndv(), Some(11));
+
+        let lpbound = lpbound(vec![r, relation("S", "Y", vec![1; 20])]);
+        assert_eq!(lpbound.estimate_union("R", "S"), Ok(31.0));
+        assert_eq!(lpbound.estimate_union("S", "R"), Ok(31.0));
+    }
+
+    #[test]
+    fn report_json_escapes_control_characters() {
+        let lpbound = lpbound(vec![relation("R\tx", "a\"b\\\nc\r\u{1}", vec![2])]);
+        let json = generate_statistics_report(&lpbound).to_json();
+        assert_eq!(
+            json,
+            "[{\"relation\":\"R\\tx\",\"attribute\":\"a\\\"b\\\\\\nc\\r\\u0001\",\"cardinality\":2,\
+             \"max_degree\":2,\"l2_norm\":2,\"ndv\":1,\"skewed\":false}]"
+        );
+        assert!(!json.chars().any(|c| c.is_control()));
+    }
+
+    #[test]
+    fn breakdown_with_an_empty_side_has_defined_ratio() {
+        let lpbound = lpbound(vec![relation("R", "Y", vec![4, 3, 1]), relation("S", "Y", vec![])]);
+        let breakdown = lpbound.estimate_with_breakdown(&join("R", "Y", "S", "Y"));
+        assert_eq!(breakdown.estimate(), 0.0);
+        assert_eq!(breakdown.tightness_ratio, 1.0);
+    }
+
+    #[test]
+    fn csv_statistics_with_quoted_values() {
+        let path = temp_csv(
+            "quoted",
+            "attr_name,value,frequency\ncity,\"Paris, France\",3\ncity, \"Paris, France\" ,2\ncity,\"say \"\"hi\"\"\",1\n",
+        );
+        let relation = load_relation_from_csv(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(relation.degree_sequences["city"].degrees, vec![5, 1]);
+
+        let path = temp_csv("unquoted_comma", "attr_name,value,frequency\ncity,Paris, France,3\n");
+        let unquoted = load_relation_from_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(unquoted, Err(StatsError::Parse { line: 2, message }) if message.contains("found 4")));
+
+        let path = temp_csv("unterminated", "attr_name,value,frequency\ncity,\"Paris,3\n");
+        let unterminated = load_relation_from_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(unterminated, Err(StatsError::Parse { line: 2, message }) if message.contains("unterminated")));
+    }
 }
@@ -0,0 +1,56 @@
+use arrow::array::{Int32Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dag_faas::arrow_util::{columnar_groupby_sum, transactions_schema};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// 10,000 transactions spread over 50 countries
+fn transactions(rows: usize) -> RecordBatch {
+    let countries: Vec<String> = (0..rows).map(|i| format!("C{}", (i * 7919) % 50)).collect();
+    RecordBatch::try_new(
+        Arc::new(transactions_schema()),
+        vec![
+            Arc::new(Int32Array::from_iter_values(0..rows as i32)),
+            Arc::new(Int32Array::from_iter_values((0..rows as i32).map(|i| i % 1000))),
+            Arc::new(StringArray::from(countries)),
+        ],
+    )
+    .unwrap()
+}
+
+// groupby_sum as it was before the columnar rewrite: one HashMap probe per row
+fn hashmap_groupby_sum(batch: &RecordBatch) -> RecordBatch {
+    let len = batch.num_rows();
+    let country_array = batch.column(2).as_any().downcast_ref::<StringArray>().unwrap();
+    let usd_array = batch.column(1).as_any().downcast_ref::<Int32Array>().unwrap();
+
+    let mut sum_map = HashMap::new();
+    for i in 0..len {
+        let country = country_array.value(i);
+        let usd = usd_array.value(i);
+        *sum_map.entry(country).or_insert(0) += usd;
+    }
+    let countries: Vec<&str> = sum_map.keys().cloned().collect();
+    let usds: Vec<i32> = countries.iter().map(|c| sum_map[*c]).collect();
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("country", DataType::Utf8, false),
+        Field::new("usd_sum", DataType::Int32, false),
+    ]));
+    RecordBatch::try_new(schema, vec![Arc::new(StringArray::from(countries)), Arc::new(Int32Array::from(usds))]).unwrap()
+}
+
+fn groupby_sum(c: &mut Criterion) {
+    let batch = transactions(10_000);
+    let mut group = c.benchmark_group("groupby_sum_10k_rows");
+    group.bench_function("hashmap_rows", |b| b.iter(|| hashmap_groupby_sum(black_box(&batch))));
+    group.bench_function("columnar", |b| {
+        b.iter(|| columnar_groupby_sum(black_box(&batch), "country", "usd", "usd_sum").unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, groupby_sum);
+criterion_main!(benches);
@@ -1,16 +1,82 @@
-use arrow::array::{Int32Array, StringArray};
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::array::{Array, ArrayRef, Date32Array, Date64Array, Int32Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
 use arrow::record_batch::RecordBatch;
+use arrow::temporal_conversions::{date32_to_datetime, date64_to_datetime};
+use crate::dag::CycleError;
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, TimeDelta};
+use std::str::FromStr;
 use std::sync::Arc;
 
-pub fn make_sample_batch() -> RecordBatch {
-    let schema = Arc::new(Schema::new(vec![
+// Schema of the sample transactions table, kept by filter_country
+pub fn transactions_schema() -> Schema {
+    Schema::new(vec![
         Field::new("id", DataType::Int32, false),
         Field::new("usd", DataType::Int32, false),
         Field::new("country", DataType::Utf8, false),
-    ]));
+    ])
+}
+
+// Schema produced by groupby_sum
+pub fn usd_by_country_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("country", DataType::Utf8, false),
+        Field::new("usd_sum", DataType::Int32, false),
+    ])
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    // A node expects a different schema than its parent produces
+    InputMismatch { node: String, parent: String, expected: SchemaRef, found: SchemaRef },
+    // A source node has no known schema
+    UnknownSource(String),
+    // A node runs an operation whose schema effect is unknown
+    UnknownOp { node: String, op: String },
+    // A node reads a column its input does not have
+    MissingColumn { node: String, column: String },
+    // The DAG does not have exactly one sink node
+    AmbiguousOutput(Vec<String>),
+    // A batch does not have the schema it was expected to have
+    SchemaMismatch { expected: SchemaRef, found: SchemaRef },
+    // A batch could not be decoded
+    Arrow(ArrowError),
+    // Schemas cannot be propagated through a cyclic graph
+    Cycle(CycleError),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::InputMismatch { node, parent, expected, found } => write!(
+                f,
+                "node {} expects input schema {:?} but parent {} produces {:?}",
+                node, expected, parent, found
+            ),
+            SchemaError::UnknownSource(node) => write!(f, "no schema known for source {}", node),
+            SchemaError::UnknownOp { node, op } => write!(f, "node {} has unknown op {}", node, op),
+            SchemaError::MissingColumn { node, column } => write!(f, "node {} reads missing column {}", node, column),
+            SchemaError::AmbiguousOutput(sinks) => write!(f, "expected one output node, found [{}]", sinks.join(", ")),
+            SchemaError::SchemaMismatch { expected, found } => {
+                write!(f, "expected schema {:?} but found {:?}", expected, found)
+            }
+            SchemaError::Arrow(err) => write!(f, "cannot decode batch: {}", err),
+            SchemaError::Cycle(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl From<CycleError> for SchemaError {
+    fn from(err: CycleError) -> Self {
+        SchemaError::Cycle(err)
+    }
+}
+
+pub fn make_sample_batch() -> RecordBatch {
     RecordBatch::try_new(
-        schema,
+        Arc::new(transactions_schema()),
         vec![
             Arc::new(Int32Array::from(vec![1, 2, 3, 4])),
             Arc::new(Int32Array::from(vec![100, 200, 150, 300])),
@@ -31,13 +97,8 @@ pub fn filter_country(batch: &RecordBatch, country: &str) -> RecordBatch {
     let filtered_usd: Vec<i32> = usd_array.iter().enumerate().filter_map(|(i, v)| if mask[i] { v } else { None }).collect();
     let filtered_country: Vec<&str> = country_array.iter().enumerate().filter_map(|(i, v)| if mask[i] { Some(v.unwrap()) } else { None }).collect();
 
-    let schema = Arc::new(Schema::new(vec![
-        Field::new("id", DataType::Int32, false),
-        Field::new("usd", DataType::Int32, false),
-        Field::new("country", DataType::Utf8, false),
-    ]));
     RecordBatch::try_new(
-        schema,
+        Arc::new(transactions_schema()),
         vec![
             Arc::new(Int32Array::from(filtered_id)),
             Arc::new(Int32Array::from(filtered_usd)),
@@ -46,31 +107,440 @@ pub fn filter_country(batch: &RecordBatch, country: &str) -> RecordBatch {
     ).unwrap()
 }
 
-pub fn groupby_sum(batch: &RecordBatch) -> RecordBatch {
-    let len = batch.num_rows();
-    let country_array = batch.column(2).as_any().downcast_ref::<StringArray>().unwrap();
-    let usd_array = batch.column(1).as_any().downcast_ref::<Int32Array>().unwrap();
+pub fn groupby_sum(batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+    columnar_groupby_sum(batch, "country", "usd", "usd_sum")
+}
+
+// A group key. Keys shorter than 8 bytes are packed together with their length into one integer,
+// which covers NULL and the short strings and numbers most group columns hold.
+enum GroupKey<'a> {
+    Packed(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> GroupKey<'a> {
+    fn new(key: Option<&'a [u8]>) -> Self {
+        match key {
+            // The length byte of a packed key is at most 7, so this is no other key
+            None => GroupKey::Packed(u64::MAX),
+            Some(bytes) if bytes.len() < 8 => GroupKey::Packed(
+                bytes.iter().enumerate().fold((bytes.len() as u64) << 56, |word, (i, &b)| word | (b as u64) << (8 * i)),
+            ),
+            Some(bytes) => GroupKey::Bytes(bytes),
+        }
+    }
+
+    // The key of the string data[start..end]. A short string with 8 bytes of buffer after its
+    // start is read as a single word and masked to its length, skipping the byte-by-byte copy.
+    fn string(data: &'a [u8], start: usize, end: usize) -> Self {
+        let len = end - start;
+        match data.get(start..start + 8) {
+            Some(word) if len < 8 => {
+                let word = u64::from_le_bytes(word.try_into().expect("slice of 8 bytes"));
+                GroupKey::Packed(word & ((1 << (8 * len)) - 1) | (len as u64) << 56)
+            }
+            _ => GroupKey::new(Some(&data[start..end])),
+        }
+    }
+}
+
+// Open-addressing hash table from packed keys to group ids with linear probing; a lookup is one
+// multiply and usually one integer comparison
+struct PackedGroups {
+    keys: Vec<u64>,
+    ids: Vec<u32>,
+    len: usize,
+}
 
-    let mut sum_map = std::collections::HashMap::new();
-    for i in 0..len {
-        let country = country_array.value(i);
-        let usd = usd_array.value(i);
-        *sum_map.entry(country).or_insert(0) += usd;
+impl PackedGroups {
+    // Not a packed key either: its length byte is 0xff
+    const EMPTY: u64 = u64::MAX - 1;
+
+    fn with_capacity(capacity: usize) -> Self {
+        let slots = (capacity * 2).next_power_of_two().max(16);
+        PackedGroups { keys: vec![Self::EMPTY; slots], ids: vec![0; slots], len: 0 }
     }
-    let countries: Vec<&str> = sum_map.keys().cloned().collect();
-    let usds: Vec<i32> = countries.iter().map(|c| sum_map[*c]).collect();
 
+    #[inline]
+    fn get_or_insert(&mut self, key: u64, id: u32) -> u32 {
+        let mask = self.keys.len() - 1;
+        // Fibonacci hashing: the multiply moves every key byte into the high bits
+        let mut slot = (key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40) as usize & mask;
+        while self.keys[slot] != Self::EMPTY {
+            if self.keys[slot] == key {
+                return self.ids[slot];
+            }
+            slot = (slot + 1) & mask;
+        }
+
+        self.keys[slot] = key;
+        self.ids[slot] = id;
+        self.len += 1;
+        if self.len * 2 > self.keys.len() {
+            self.grow();
+        }
+        id
+    }
+
+    #[cold]
+    fn grow(&mut self) {
+        let mut grown = PackedGroups::with_capacity(self.keys.len());
+        for (&key, &id) in self.keys.iter().zip(&self.ids).filter(|&(&key, _)| key != Self::EMPTY) {
+            grown.get_or_insert(key, id);
+        }
+        *self = grown;
+    }
+}
+
+// Dense group ids for a column of keys, numbered in order of first appearance, and the row each
+// group first appears in
+fn group_ids<'a>(keys: impl Iterator<Item = GroupKey<'a>>) -> (Vec<u32>, Vec<u32>) {
+    let mut ids = Vec::with_capacity(keys.size_hint().0);
+    let mut first_rows = Vec::new();
+    let mut packed = PackedGroups::with_capacity(64);
+    let mut long: std::collections::HashMap<&[u8], u32> = std::collections::HashMap::new();
+    for (row, key) in keys.enumerate() {
+        let next = first_rows.len() as u32;
+        let id = match key {
+            GroupKey::Packed(key) => packed.get_or_insert(key, next),
+            GroupKey::Bytes(key) => *long.entry(key).or_insert(next),
+        };
+        if id == next {
+            first_rows.push(row as u32);
+        }
+        ids.push(id);
+    }
+    (ids, first_rows)
+}
+
+// SUM(value_col) GROUP BY group_col, output sorted by group_col. One pass over the key column
+// assigns every row a dense group id, a second pass adds the values into a flat vector indexed
+// by group. The sum keeps the value column's type.
+pub fn columnar_groupby_sum(batch: &RecordBatch, group_col: &str, value_col: &str, output_col: &str) -> Result<RecordBatch, ArrowError> {
+    use arrow::array::{AsArray, UInt32Array};
+    use arrow::datatypes::Int64Type;
+    use arrow::row::{RowConverter, SortField};
+    let key_field = batch.schema().field_with_name(group_col)?.clone();
+    let value_field = batch.schema().field_with_name(value_col)?.clone();
+
+    let keys = column(batch, group_col)?;
+    let (ids, first_rows) = match keys.data_type() {
+        DataType::Utf8 => {
+            let keys = keys.as_string::<i32>();
+            let (offsets, data) = (keys.value_offsets(), keys.value_data());
+            group_ids((0..keys.len()).map(|row| {
+                if keys.is_valid(row) {
+                    GroupKey::string(data, offsets[row] as usize, offsets[row + 1] as usize)
+                } else {
+                    GroupKey::new(None)
+                }
+            }))
+        }
+        // Arrow's row format gives keys of any other type, NULL included, bytes of their own
+        other => {
+            let converter = RowConverter::new(vec![SortField::new(other.clone())])?;
+            let rows = converter.convert_columns(std::slice::from_ref(keys))?;
+            group_ids(rows.iter().map(|row| GroupKey::new(Some(row.data()))))
+        }
+    };
+
+    let values = arrow::compute::cast(column(batch, value_col)?, &DataType::Int64)?;
+    let values = values.as_primitive::<Int64Type>();
+    let mut sums = vec![0i64; first_rows.len()];
+    let mut has_values = vec![false; first_rows.len()];
+    for (row, (&id, &value)) in ids.iter().zip(values.values()).enumerate() {
+        if values.null_count() == 0 || values.is_valid(row) {
+            sums[id as usize] = sums[id as usize].wrapping_add(value);
+            has_values[id as usize] = true;
+        }
+    }
+    let sums: Int64Array = sums.into_iter().zip(has_values).map(|(sum, valid)| valid.then_some(sum)).collect();
+
+    let group_keys = arrow::compute::take(keys, &UInt32Array::from(first_rows), None)?;
+    let group_sums = arrow::compute::cast(&sums, value_field.data_type())?;
     let schema = Arc::new(Schema::new(vec![
-        Field::new("country", DataType::Utf8, false),
-        Field::new("usd_sum", DataType::Int32, false),
+        key_field,
+        Field::new(output_col, value_field.data_type().clone(), value_field.is_nullable()),
     ]));
-    RecordBatch::try_new(
-        schema,
-        vec![
-            Arc::new(StringArray::from(countries)),
-            Arc::new(Int32Array::from(usds)),
-        ],
-    ).unwrap()
+    sort_batch(&RecordBatch::try_new(schema, vec![group_keys, group_sums])?, &[group_col])
+}
+
+// The named column, or a schema error if the batch has no such column
+fn column<'a>(batch: &'a RecordBatch, col: &str) -> Result<&'a ArrayRef, ArrowError> {
+    Ok(batch.column(batch.schema().index_of(col)?))
+}
+
+// The named column as a Utf8 array
+fn string_column<'a>(batch: &'a RecordBatch, col: &str) -> Result<&'a StringArray, ArrowError> {
+    column(batch, col)?
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or_else(|| ArrowError::InvalidArgumentError(format!("column {} is not Utf8", col)))
+}
+
+// Replace the named column, keeping the schema unchanged
+fn replace_column(batch: &RecordBatch, col: &str, array: ArrayRef) -> Result<RecordBatch, ArrowError> {
+    let idx = batch.schema().index_of(col)?;
+    let mut columns = batch.columns().to_vec();
+    columns[idx] = array;
+    RecordBatch::try_new(batch.schema(), columns)
+}
+
+// Apply a string function to every value of a Utf8 column
+fn map_string_column(batch: &RecordBatch, col: &str, f: impl Fn(&str) -> String) -> Result<RecordBatch, ArrowError> {
+    let mapped: StringArray = string_column(batch, col)?.iter().map(|v| v.map(&f)).collect();
+    replace_column(batch, col, Arc::new(mapped))
+}
+
+pub fn str_upper(batch: &RecordBatch, col: &str) -> Result<RecordBatch, ArrowError> {
+    map_string_column(batch, col, |s| s.to_uppercase())
+}
+
+pub fn str_lower(batch: &RecordBatch, col: &str) -> Result<RecordBatch, ArrowError> {
+    map_string_column(batch, col, |s| s.to_lowercase())
+}
+
+pub fn str_trim(batch: &RecordBatch, col: &str) -> Result<RecordBatch, ArrowError> {
+    map_string_column(batch, col, |s| s.trim().to_string())
+}
+
+// Substring of `length` characters starting at 0-based character `start`
+pub fn str_substr(batch: &RecordBatch, col: &str, start: i64, length: u64) -> Result<RecordBatch, ArrowError> {
+    use arrow::compute::kernels::substring::substring_by_char;
+    let result = substring_by_char(string_column(batch, col)?, start, Some(length))?;
+    replace_column(batch, col, Arc::new(result))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateUnit {
+    Day,
+    Month,
+    Year,
+    Second,
+}
+
+impl FromStr for DateUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(DateUnit::Day),
+            "month" => Ok(DateUnit::Month),
+            "year" => Ok(DateUnit::Year),
+            "second" => Ok(DateUnit::Second),
+            _ => Err(format!("Unknown date unit {}", s)),
+        }
+    }
+}
+
+// Read a Date32 or Date64 column as datetimes
+fn date_values(batch: &RecordBatch, col: &str) -> Result<Vec<Option<NaiveDateTime>>, ArrowError> {
+    let array = column(batch, col)?;
+    match array.data_type() {
+        DataType::Date32 => {
+            let array = array.as_any().downcast_ref::<Date32Array>().unwrap();
+            Ok(array.iter().map(|v| v.and_then(date32_to_datetime)).collect())
+        }
+        DataType::Date64 => {
+            let array = array.as_any().downcast_ref::<Date64Array>().unwrap();
+            Ok(array.iter().map(|v| v.and_then(date64_to_datetime)).collect())
+        }
+        other => Err(ArrowError::InvalidArgumentError(format!("column {} is {}, not a date", col, other))),
+    }
+}
+
+// Difference col1 - col2 counted in unit boundaries crossed, added as "<col1>_<col2>_diff"
+pub fn date_diff(batch: &RecordBatch, col1: &str, col2: &str, unit: DateUnit) -> Result<RecordBatch, ArrowError> {
+    let lhs = date_values(batch, col1)?;
+    let rhs = date_values(batch, col2)?;
+
+    let diff: Int64Array = lhs.iter().zip(rhs.iter()).map(|(a, b)| {
+        let (a, b) = (a.as_ref()?, b.as_ref()?);
+        Some(match unit {
+            DateUnit::Day => (a.date() - b.date()).num_days(),
+            DateUnit::Month => (a.year() as i64 * 12 + a.month0() as i64) - (b.year() as i64 * 12 + b.month0() as i64),
+            DateUnit::Year => a.year() as i64 - b.year() as i64,
+            DateUnit::Second => (*a - *b).num_seconds(),
+        })
+    }).collect();
+
+    let mut fields: Vec<Field> = batch.schema().fields().iter().map(|f| f.as_ref().clone()).collect();
+    fields.push(Field::new(format!("{}_{}_diff", col1, col2), DataType::Int64, true));
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(diff));
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+// Shift a date column by n units; month arithmetic clamps to the end of the month. A shift too
+// large to represent is an error, a date shifted out of range becomes NULL.
+pub fn date_add(batch: &RecordBatch, col: &str, n: i64, unit: DateUnit) -> Result<RecordBatch, ArrowError> {
+    let invalid = || ArrowError::InvalidArgumentError(format!("cannot shift {} by {} {:?} units", col, n, unit));
+    let shift: Box<dyn Fn(NaiveDateTime) -> Option<NaiveDateTime>> = match unit {
+        DateUnit::Day | DateUnit::Second => {
+            let delta = if unit == DateUnit::Day { TimeDelta::try_days(n) } else { TimeDelta::try_seconds(n) };
+            let delta = delta.ok_or_else(invalid)?;
+            Box::new(move |d| d.checked_add_signed(delta))
+        }
+        DateUnit::Month | DateUnit::Year => {
+            let m = if unit == DateUnit::Year { n.checked_mul(12) } else { Some(n) };
+            let m = m.ok_or_else(invalid)?;
+            let months = Months::new(u32::try_from(m.unsigned_abs()).map_err(|_| invalid())?);
+            if m >= 0 {
+                Box::new(move |d| d.checked_add_months(months))
+            } else {
+                Box::new(move |d| d.checked_sub_months(months))
+            }
+        }
+    };
+    let shifted: Vec<Option<NaiveDateTime>> = date_values(batch, col)?.into_iter().map(|v| v.and_then(&shift)).collect();
+
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let result: ArrayRef = match column(batch, col)?.data_type() {
+        DataType::Date32 => Arc::new(shifted.iter().map(|v| v.map(|d| (d - epoch).num_days() as i32)).collect::<Date32Array>()),
+        _ => Arc::new(shifted.iter().map(|v| v.map(|d| d.and_utc().timestamp_millis())).collect::<Date64Array>()),
+    };
+    replace_column(batch, col, result)
+}
+
+// Sort the batch by the given columns, ascending
+fn sort_batch(batch: &RecordBatch, cols: &[&str]) -> Result<RecordBatch, ArrowError> {
+    use arrow::compute::{lexsort_to_indices, take_record_batch, SortColumn};
+    let sort_columns = cols.iter().map(|c| Ok(SortColumn {
+        values: column(batch, c)?.clone(),
+        options: None,
+    })).collect::<Result<Vec<SortColumn>, ArrowError>>()?;
+    let indices = lexsort_to_indices(&sort_columns, None)?;
+    take_record_batch(batch, &indices)
+}
+
+// Row ranges of consecutive equal values in an already sorted column
+fn partition_ranges(batch: &RecordBatch, col: &str) -> Result<Vec<std::ops::Range<usize>>, ArrowError> {
+    Ok(arrow::compute::partition(&[column(batch, col)?.clone()])?.ranges())
+}
+
+// Append an Int64 column to the batch
+fn append_column(batch: &RecordBatch, name: &str, values: Vec<i64>) -> Result<RecordBatch, ArrowError> {
+    let mut fields: Vec<Field> = batch.schema().fields().iter().map(|f| f.as_ref().clone()).collect();
+    fields.push(Field::new(name, DataType::Int64, false));
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(Int64Array::from(values)));
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+// SUM(value_col) OVER (PARTITION BY partition_col), output sorted by partition_col
+pub fn window_sum(batch: &RecordBatch, value_col: &str, partition_col: &str, result_col: &str) -> Result<RecordBatch, ArrowError> {
+    let sorted = sort_batch(batch, &[partition_col])?;
+    let values = arrow::compute::cast(column(&sorted, value_col)?, &DataType::Int64)?;
+    let values = values.as_any().downcast_ref::<Int64Array>().unwrap();
+
+    let mut sums = vec![0; sorted.num_rows()];
+    for range in partition_ranges(&sorted, partition_col)? {
+        let total: i64 = range.clone().filter(|&i| values.is_valid(i)).map(|i| values.value(i)).sum();
+        sums[range].fill(total);
+    }
+    append_column(&sorted, result_col, sums)
+}
+
+// ROW_NUMBER() OVER (PARTITION BY partition_col ORDER BY order_col), output sorted by both
+pub fn row_number(batch: &RecordBatch, partition_col: &str, order_col: &str, result_col: &str) -> Result<RecordBatch, ArrowError> {
+    let sorted = sort_batch(batch, &[partition_col, order_col])?;
+
+    let mut numbers = vec![0; sorted.num_rows()];
+    for range in partition_ranges(&sorted, partition_col)? {
+        for (n, i) in range.enumerate() {
+            numbers[i] = n as i64 + 1;
+        }
+    }
+    append_column(&sorted, result_col, numbers)
+}
+
+// HyperLogLog sketch for approximate COUNT(DISTINCT); 2^12 registers give ~1.6% standard error
+pub struct HllAccumulator {
+    registers: Vec<u8>,
+}
+
+impl HllAccumulator {
+    const PRECISION: u32 = 12;
+
+    pub fn new() -> Self {
+        HllAccumulator { registers: vec![0; 1 << Self::PRECISION] }
+    }
+
+    pub fn add_hash(&mut self, hash: u64) {
+        let idx = (hash >> (64 - Self::PRECISION)) as usize;
+        // Rank of the first set bit in the remaining bits; the guard bit caps it
+        let rank = ((hash << Self::PRECISION) | (1 << (Self::PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[idx] = self.registers[idx].max(rank);
+    }
+
+    // Hash every value of the column through its row encoding, so any Arrow type works
+    pub fn update(&mut self, batch: &RecordBatch, col: &str) -> Result<(), ArrowError> {
+        use arrow::row::{RowConverter, SortField};
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        let column = column(batch, col)?.clone();
+        let converter = RowConverter::new(vec![SortField::new(column.data_type().clone())])?;
+        let rows = converter.convert_columns(&[column])?;
+        for row in rows.iter() {
+            let mut hasher = DefaultHasher::new();
+            row.as_ref().hash(&mut hasher);
+            self.add_hash(hasher.finish());
+        }
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: &HllAccumulator) {
+        for (a, b) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *a = (*a).max(*b);
+        }
+    }
+
+    pub fn estimate(&self) -> usize {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        // Small cardinalities: fall back to linear counting over empty registers
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as usize
+        } else {
+            raw.round() as usize
+        }
+    }
+}
+
+impl Default for HllAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Sketch each batch separately and merge, as a distributed COUNT(DISTINCT) would
+pub fn approx_count_distinct(batches: &[RecordBatch], col: &str) -> Result<usize, ArrowError> {
+    let mut hll = HllAccumulator::new();
+    for batch in batches {
+        let mut partial = HllAccumulator::new();
+        partial.update(batch, col)?;
+        hll.merge(&partial);
+    }
+    Ok(hll.estimate())
+}
+
+// Hash of the schema and every row's values; equal batches get equal fingerprints
+pub fn batch_fingerprint(batch: &RecordBatch) -> Result<u64, ArrowError> {
+    use arrow::row::{RowConverter, SortField};
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    batch.schema().fields().hash(&mut hasher);
+
+    let fields = batch.schema().fields().iter().map(|f| SortField::new(f.data_type().clone())).collect();
+    let converter = RowConverter::new(fields)?;
+    let rows = converter.convert_columns(batch.columns())?;
+    for row in rows.iter() {
+        row.as_ref().hash(&mut hasher);
+    }
+    Ok(hasher.finish())
 }
 
 // Arrow IPC serialization
@@ -86,9 +556,196 @@ pub fn batch_to_bytes(batch: &RecordBatch) -> Vec<u8> {
 }
 
 // Arrow IPC deserialization
-pub fn bytes_to_batch(bytes: &[u8]) -> RecordBatch {
+pub fn bytes_to_batch(bytes: &[u8]) -> Result<RecordBatch, ArrowError> {
     use arrow::ipc::reader::StreamReader;
     use std::io::Cursor;
-    let mut reader = StreamReader::try_new(Cursor::new(bytes), None).unwrap();
-    reader.next().unwrap().unwrap()
+    let mut reader = StreamReader::try_new(Cursor::new(bytes), None)?;
+    reader.next().unwrap_or_else(|| Err(ArrowError::IpcError("stream has no record batch".to_string())))
+}
+
+// Arrow IPC deserialization that also checks the batch has the expected schema
+pub fn bytes_to_batch_with_schema(bytes: &[u8], expected_schema: &Schema) -> Result<RecordBatch, SchemaError> {
+    let batch = bytes_to_batch(bytes).map_err(SchemaError::Arrow)?;
+    if batch.schema().as_ref() != expected_schema {
+        return Err(SchemaError::SchemaMismatch {
+            expected: Arc::new(expected_schema.clone()),
+            found: batch.schema(),
+        });
+    }
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_batch(values: Vec<&str>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("s", DataType::Utf8, false)]));
+        RecordBatch::try_new(schema, vec![Arc::new(StringArray::from(values))]).unwrap()
+    }
+
+    fn strings(batch: &RecordBatch, col: &str) -> Vec<String> {
+        string_column(batch, col).unwrap().iter().map(|v| v.unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn str_substr_counts_characters() {
+        let batch = str_substr(&string_batch(vec!["E=mc²", "abc"]), "s", 3, 2).unwrap();
+        assert_eq!(strings(&batch, "s"), vec!["c²", ""]);
+    }
+
+    #[test]
+    fn string_functions_reject_missing_and_non_string_columns() {
+        assert!(str_upper(&make_sample_batch(), "nope").is_err());
+        assert!(str_trim(&make_sample_batch(), "usd").is_err());
+        assert_eq!(strings(&str_lower(&make_sample_batch(), "country").unwrap(), "country"), vec!["us", "it", "it", "fr"]);
+    }
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    // Date32 columns "end" and "start" from (end, start) pairs
+    fn date_batch(pairs: &[(NaiveDate, NaiveDate)]) -> RecordBatch {
+        let days = |date: NaiveDate| (date - ymd(1970, 1, 1)).num_days() as i32;
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("end", DataType::Date32, false),
+            Field::new("start", DataType::Date32, false),
+        ]));
+        let end: Date32Array = pairs.iter().map(|&(end, _)| Some(days(end))).collect();
+        let start: Date32Array = pairs.iter().map(|&(_, start)| Some(days(start))).collect();
+        RecordBatch::try_new(schema, vec![Arc::new(end), Arc::new(start)]).unwrap()
+    }
+
+    fn int64s(batch: &RecordBatch, col: &str) -> Vec<i64> {
+        let array = column(batch, col).unwrap().as_any().downcast_ref::<Int64Array>().unwrap();
+        array.values().to_vec()
+    }
+
+    #[test]
+    fn date_diff_days_across_month_and_year_boundaries() {
+        let batch = date_batch(&[
+            (ymd(2024, 3, 1), ymd(2024, 2, 28)),  // leap year February
+            (ymd(2023, 3, 1), ymd(2023, 2, 28)),
+            (ymd(2024, 1, 1), ymd(2023, 12, 31)), // year boundary
+            (ymd(2023, 12, 31), ymd(2024, 1, 31)),
+        ]);
+        let result = date_diff(&batch, "end", "start", DateUnit::Day).unwrap();
+        assert_eq!(int64s(&result, "end_start_diff"), vec![2, 1, 1, -31]);
+
+        let months = date_diff(&batch, "end", "start", DateUnit::Month).unwrap();
+        assert_eq!(int64s(&months, "end_start_diff"), vec![1, 1, 1, -1]);
+    }
+
+    #[test]
+    fn date_add_clamps_to_month_end() {
+        let batch = date_batch(&[(ymd(2024, 1, 31), ymd(2024, 1, 1))]);
+        let shifted = date_add(&batch, "end", 1, DateUnit::Month).unwrap();
+        let diff = date_diff(&shifted, "end", "start", DateUnit::Day).unwrap();
+        assert_eq!(int64s(&diff, "end_start_diff"), vec![59]); // 2024-02-29
+        assert!(date_add(&batch, "nope", 1, DateUnit::Day).is_err());
+        assert!(date_diff(&make_sample_batch(), "id", "usd", DateUnit::Day).is_err());
+    }
+
+    #[test]
+    fn date_add_rejects_unrepresentable_shifts() {
+        let batch = date_batch(&[(ymd(1970, 1, 1), ymd(1970, 1, 1))]);
+        assert!(date_add(&batch, "end", 1_000_000_000_000_000_000, DateUnit::Year).is_err());
+        assert!(date_add(&batch, "end", i64::MAX, DateUnit::Day).is_err());
+        // 2^32 + 12 months must not wrap around to 12 months
+        assert!(date_add(&batch, "end", (1 << 32) + 12, DateUnit::Month).is_err());
+
+        // A representable shift that leaves chrono's date range gives NULL, which a non-nullable
+        // column rejects
+        assert!(date_add(&batch, "end", 1_000_000, DateUnit::Year).is_err());
+        let schema = Arc::new(Schema::new(vec![Field::new("d", DataType::Date32, true)]));
+        let nullable = RecordBatch::try_new(schema, vec![Arc::new(Date32Array::from(vec![0]))]).unwrap();
+        let shifted = date_add(&nullable, "d", 1_000_000, DateUnit::Year).unwrap();
+        assert!(column(&shifted, "d").unwrap().is_null(0));
+    }
+
+    fn int32s(batch: &RecordBatch, col: &str) -> Vec<i32> {
+        let array = column(batch, col).unwrap().as_any().downcast_ref::<Int32Array>().unwrap();
+        array.values().to_vec()
+    }
+
+    #[test]
+    fn window_functions_over_two_partitions() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("p", DataType::Utf8, false),
+            Field::new("v", DataType::Int32, false),
+        ]));
+        let batch = RecordBatch::try_new(schema, vec![
+            Arc::new(StringArray::from(vec!["b", "a", "b", "a", "a"])),
+            Arc::new(Int32Array::from(vec![5, 1, 2, 3, 2])),
+        ]).unwrap();
+
+        let summed = window_sum(&batch, "v", "p", "total").unwrap();
+        assert_eq!(strings(&summed, "p"), vec!["a", "a", "a", "b", "b"]);
+        assert_eq!(int64s(&summed, "total"), vec![6, 6, 6, 7, 7]);
+
+        let numbered = row_number(&batch, "p", "v", "rn").unwrap();
+        assert_eq!(int32s(&numbered, "v"), vec![1, 2, 3, 2, 5]);
+        assert_eq!(int64s(&numbered, "rn"), vec![1, 2, 3, 1, 2]);
+
+        assert!(window_sum(&batch, "nope", "p", "total").is_err());
+    }
+
+    #[test]
+    fn approx_count_distinct_on_sample_batch() {
+        // 4 rows, 3 distinct countries
+        let estimate = approx_count_distinct(&[make_sample_batch()], "country").unwrap();
+        assert!((2..=4).contains(&estimate), "estimate {}", estimate);
+
+        // Merging sketches of the same batch does not double count
+        let batches = vec![make_sample_batch(), make_sample_batch()];
+        assert_eq!(approx_count_distinct(&batches, "country").unwrap(), estimate);
+        assert!(approx_count_distinct(&batches, "nope").is_err());
+    }
+
+    #[test]
+    fn fingerprint_rejects_unsupported_columns() {
+        assert_eq!(batch_fingerprint(&make_sample_batch()).unwrap(), batch_fingerprint(&make_sample_batch()).unwrap());
+
+        use arrow::array::FixedSizeListArray;
+        use arrow::datatypes::Int32Type;
+        let list = FixedSizeListArray::from_iter_primitive::<Int32Type, _, _>(vec![Some(vec![Some(1), Some(2)])], 2);
+        let batch = RecordBatch::try_from_iter(vec![("pair", Arc::new(list) as ArrayRef)]).unwrap();
+        assert!(batch_fingerprint(&batch).is_err());
+    }
+
+    #[test]
+    fn columnar_groupby_sum_groups_sorted_keys_and_nulls() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("k", Arc::new(StringArray::from(vec![Some("b"), None, Some("a"), Some("b"), None, Some("c")])) as ArrayRef),
+            ("v", Arc::new(Int32Array::from(vec![Some(1), Some(2), Some(3), Some(4), Some(5), None])) as ArrayRef),
+        ])
+        .unwrap();
+        let result = columnar_groupby_sum(&batch, "k", "v", "total").unwrap();
+
+        let keys: Vec<Option<&str>> = string_column(&result, "k").unwrap().iter().collect();
+        assert_eq!(keys, vec![None, Some("a"), Some("b"), Some("c")]);
+        let totals: Vec<Option<i32>> = column(&result, "total").unwrap().as_any().downcast_ref::<Int32Array>().unwrap().iter().collect();
+        assert_eq!(totals, vec![Some(7), Some(3), Some(5), None]);
+        assert_eq!(result.schema().field_with_name("total").unwrap().data_type(), &DataType::Int32);
+    }
+
+    #[test]
+    fn columnar_groupby_sum_long_and_non_string_keys() {
+        // Keys of 8 bytes and more are hashed as slices, "abcdefg" is the longest packed key
+        let batch = RecordBatch::try_from_iter(vec![
+            ("k", Arc::new(StringArray::from(vec!["abcdefgh", "abcdefg", "abcdefgh", "abcdefghij"])) as ArrayRef),
+            ("n", Arc::new(Int32Array::from(vec![Some(7), None, Some(7), Some(-1)])) as ArrayRef),
+            ("v", Arc::new(Int64Array::from(vec![1, 2, 3, 4])) as ArrayRef),
+        ])
+        .unwrap();
+        let by_string = columnar_groupby_sum(&batch, "k", "v", "total").unwrap();
+        assert_eq!(strings(&by_string, "k"), vec!["abcdefg", "abcdefgh", "abcdefghij"]);
+        assert_eq!(int64s(&by_string, "total"), vec![2, 4, 4]);
+
+        let by_number = columnar_groupby_sum(&batch, "n", "v", "total").unwrap();
+        let keys: Vec<Option<i32>> = column(&by_number, "n").unwrap().as_any().downcast_ref::<Int32Array>().unwrap().iter().collect();
+        assert_eq!(keys, vec![None, Some(-1), Some(7)]);
+        assert_eq!(int64s(&by_number, "total"), vec![2, 4, 4]);
+    }
 }
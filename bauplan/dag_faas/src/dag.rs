@@ -1,17 +1,370 @@
+use arrow::datatypes::Schema;
+use arrow::datatypes::{DataType, Field};
+use petgraph::algo::{is_cyclic_directed, toposort};
 use petgraph::graph::{DiGraph, NodeIndex};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use crate::arrow_util::{transactions_schema, usd_by_country_schema, SchemaError};
+use crate::dag_proto::dag_proto::task_request::Op;
+use crate::dag_proto::dag_proto::{FilterCountryOp, GroupBySumOp, SourceOp};
+
+// The operation a node runs on its inputs
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DagOp {
+    Source,
+    FilterCountry(String),
+    GroupBySum,
+    // Any other worker operation, as "op:arg1:arg2", e.g. "str_upper:country"
+    Custom(String),
+}
+
+impl std::fmt::Display for DagOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DagOp::Source => write!(f, "source"),
+            DagOp::FilterCountry(country) => write!(f, "filter_country:{}", country),
+            DagOp::GroupBySum => write!(f, "groupby_sum"),
+            DagOp::Custom(code) => write!(f, "{}", code),
+        }
+    }
+}
+
+impl From<DagOp> for Op {
+    fn from(op: DagOp) -> Self {
+        match op {
+            DagOp::Source => Op::Source(SourceOp {}),
+            DagOp::FilterCountry(country) => Op::FilterCountry(FilterCountryOp { country }),
+            DagOp::GroupBySum => Op::GroupbySum(GroupBySumOp {}),
+            DagOp::Custom(code) => Op::Custom(code),
+        }
+    }
+}
+
+impl From<Op> for DagOp {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::Source(_) => DagOp::Source,
+            Op::FilterCountry(filter) => DagOp::FilterCountry(filter.country),
+            Op::GroupbySum(_) => DagOp::GroupBySum,
+            Op::Custom(code) => DagOp::Custom(code),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct DagNode {
     pub id: String,
-    pub code: String, // "source" | "filter_country" | "groupby_sum"
+    pub op: DagOp,
+    pub input_schema: Option<Schema>,
+    pub output_schema: Option<Schema>,
+}
+
+#[derive(Debug)]
+pub enum CycleError {
+    // Node ids along the cycle, starting and ending with the same node
+    Cycle(Vec<String>),
+}
+
+impl std::fmt::Display for CycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CycleError::Cycle(ids) => write!(f, "DAG has a cycle: {}", ids.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+// Report the node ids of a cycle if the graph is not acyclic
+pub fn detect_cycle(dag: &DiGraph<DagNode, ()>) -> Result<(), CycleError> {
+    if !is_cyclic_directed(dag) {
+        return Ok(());
+    }
+
+    // DFS keeping the current path; an edge back into the path closes the cycle
+    fn visit(dag: &DiGraph<DagNode, ()>, idx: NodeIndex, path: &mut Vec<NodeIndex>, done: &mut HashSet<NodeIndex>) -> Option<Vec<NodeIndex>> {
+        path.push(idx);
+        for next in dag.neighbors_directed(idx, petgraph::Outgoing) {
+            if let Some(pos) = path.iter().position(|&n| n == next) {
+                let mut cycle = path[pos..].to_vec();
+                cycle.push(next);
+                return Some(cycle);
+            }
+            if !done.contains(&next) && let Some(cycle) = visit(dag, next, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(idx);
+        None
+    }
+
+    let mut done = HashSet::new();
+    for idx in dag.node_indices() {
+        if !done.contains(&idx) && let Some(cycle) = visit(dag, idx, &mut Vec::new(), &mut done) {
+            return Err(CycleError::Cycle(cycle.into_iter().map(|n| dag[n].id.clone()).collect()));
+        }
+    }
+    unreachable!("is_cyclic_directed found a cycle that DFS did not")
+}
+
+// Check that every node's declared input schema matches what its parents declare as output
+pub fn validate_dag_schemas(dag: &DiGraph<DagNode, ()>) -> Result<(), Vec<SchemaError>> {
+    let mut errors = Vec::new();
+    for idx in dag.node_indices() {
+        let node = &dag[idx];
+        let Some(expected) = &node.input_schema else { continue };
+        for parent_idx in dag.neighbors_directed(idx, petgraph::Incoming) {
+            let parent = &dag[parent_idx];
+            if let Some(found) = &parent.output_schema && found != expected {
+                errors.push(SchemaError::InputMismatch {
+                    node: node.id.clone(),
+                    parent: parent.id.clone(),
+                    expected: Arc::new(expected.clone()),
+                    found: Arc::new(found.clone()),
+                });
+            }
+        }
+    }
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
 }
 
 pub fn build_sample_dag() -> (DiGraph<DagNode, ()>, NodeIndex) {
     let mut dag = DiGraph::<DagNode, ()>::new();
-    let idx_transactions = dag.add_node(DagNode { id: "transactions".to_string(), code: "source".to_string() });
-    let idx_euro = dag.add_node(DagNode { id: "euro_selection".to_string(), code: "filter_country".to_string() });
-    let idx_usd = dag.add_node(DagNode { id: "usd_by_country".to_string(), code: "groupby_sum".to_string() });
+    let idx_transactions = dag.add_node(DagNode {
+        id: "transactions".to_string(),
+        op: DagOp::Source,
+        input_schema: None,
+        output_schema: Some(transactions_schema()),
+    });
+    let idx_euro = dag.add_node(DagNode {
+        id: "euro_selection".to_string(),
+        op: DagOp::FilterCountry("IT".to_string()),
+        input_schema: Some(transactions_schema()),
+        output_schema: Some(transactions_schema()),
+    });
+    let idx_usd = dag.add_node(DagNode {
+        id: "usd_by_country".to_string(),
+        op: DagOp::GroupBySum,
+        input_schema: Some(transactions_schema()),
+        output_schema: Some(usd_by_country_schema()),
+    });
     dag.add_edge(idx_transactions, idx_euro, ());
     dag.add_edge(idx_euro, idx_usd, ());
+    detect_cycle(&dag).unwrap();
     (dag, idx_transactions)
 }
+
+// Counts the distinct countries of the same transactions source as the sample DAG
+pub fn build_distinct_countries_dag() -> DiGraph<DagNode, ()> {
+    let mut dag = DiGraph::<DagNode, ()>::new();
+    let idx_transactions = dag.add_node(DagNode {
+        id: "transactions".to_string(),
+        op: DagOp::Source,
+        input_schema: None,
+        output_schema: Some(transactions_schema()),
+    });
+    let idx_countries = dag.add_node(DagNode {
+        id: "distinct_countries".to_string(),
+        op: DagOp::Custom("approx_count_distinct:country".to_string()),
+        input_schema: Some(transactions_schema()),
+        output_schema: Some(Schema::new(vec![Field::new("approx_count_distinct", DataType::Int64, false)])),
+    });
+    dag.add_edge(idx_transactions, idx_countries, ());
+    detect_cycle(&dag).unwrap();
+    dag
+}
+
+// Combine two DAGs, sharing nodes with the same (id, op) so common sources run once. Fails if
+// the shared nodes close a cycle, e.g. a -> b in one DAG and b -> a in the other.
+pub fn merge_dags(a: DiGraph<DagNode, ()>, b: DiGraph<DagNode, ()>) -> Result<DiGraph<DagNode, ()>, CycleError> {
+    let mut merged = DiGraph::<DagNode, ()>::new();
+    let mut by_key: HashMap<(String, DagOp), NodeIndex> = HashMap::new();
+
+    for dag in [a, b] {
+        let mut remap: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        for idx in dag.node_indices() {
+            let node = &dag[idx];
+            let key = (node.id.clone(), node.op.clone());
+            let new_idx = *by_key.entry(key).or_insert_with(|| merged.add_node(node.clone()));
+            remap.insert(idx, new_idx);
+        }
+        for edge in dag.raw_edges() {
+            merged.update_edge(remap[&edge.source()], remap[&edge.target()], ());
+        }
+    }
+
+    detect_cycle(&merged)?;
+    Ok(merged)
+}
+
+// Schema a node produces from its input schema, following the worker's op semantics
+fn apply_op_schema(node: &DagNode, input: Option<&Schema>, source_schemas: &HashMap<String, Schema>) -> Result<Schema, SchemaError> {
+    let unknown_op = || SchemaError::UnknownOp { node: node.id.clone(), op: node.op.to_string() };
+    let input = match (&node.op, input) {
+        (DagOp::Source, _) => {
+            return source_schemas.get(&node.id).cloned().ok_or_else(|| SchemaError::UnknownSource(node.id.clone()));
+        }
+        (_, Some(input)) => input,
+        (_, None) => return Err(unknown_op()),
+    };
+    let field = |name: &str| input.field_with_name(name).cloned().map_err(|_| SchemaError::MissingColumn {
+        node: node.id.clone(),
+        column: name.to_string(),
+    });
+    let code = match &node.op {
+        DagOp::Source => unreachable!("sources return above"),
+        // filter_country reads the transactions columns by position
+        DagOp::FilterCountry(_) => {
+            for column in ["id", "usd", "country"] {
+                field(column)?;
+            }
+            let expected = transactions_schema();
+            if input.fields() != expected.fields() {
+                return Err(SchemaError::SchemaMismatch { expected: Arc::new(expected), found: Arc::new(input.clone()) });
+            }
+            return Ok(expected);
+        }
+        // groupby_sum is columnar_groupby_sum over fixed columns
+        DagOp::GroupBySum => "columnar_groupby_sum:country:usd:usd_sum",
+        DagOp::Custom(code) => code.as_str(),
+    };
+    let mut parts = code.split(':');
+    let op = parts.next().unwrap_or_default();
+    let args: Vec<&str> = parts.collect();
+
+    let with_column = |name: String, nullable: bool| {
+        let mut fields: Vec<Field> = input.fields().iter().map(|f| f.as_ref().clone()).collect();
+        fields.push(Field::new(name, DataType::Int64, nullable));
+        Schema::new(fields)
+    };
+
+    match (op, args.as_slice()) {
+        ("str_upper" | "str_lower" | "str_trim" | "str_substr" | "date_add", [col, ..]) => field(col).map(|_| input.clone()),
+        ("columnar_groupby_sum", [group, value, output]) => {
+            let value = field(value)?;
+            Ok(Schema::new(vec![field(group)?, Field::new(*output, value.data_type().clone(), value.is_nullable())]))
+        }
+        ("date_diff", [col1, col2, _]) => {
+            field(col1)?;
+            field(col2)?;
+            Ok(with_column(format!("{}_{}_diff", col1, col2), true))
+        }
+        ("window_sum", [value, partition, result]) | ("row_number", [partition, value, result]) => {
+            field(value)?;
+            field(partition)?;
+            Ok(with_column(result.to_string(), false))
+        }
+        ("approx_count_distinct", [col]) => {
+            field(col)?;
+            Ok(Schema::new(vec![Field::new("approx_count_distinct", DataType::Int64, false)]))
+        }
+        _ => Err(unknown_op()),
+    }
+}
+
+// Propagate schemas from the sources through every node and return the single sink's schema,
+// without running anything
+pub fn infer_output_schema(dag: &DiGraph<DagNode, ()>, source_schemas: &HashMap<String, Schema>) -> Result<Schema, SchemaError> {
+    detect_cycle(dag)?;
+    let topo = toposort(dag, None).expect("detect_cycle found no cycle");
+    let mut schemas: HashMap<NodeIndex, Schema> = HashMap::new();
+    for idx in topo {
+        // Operations read their first input, as the worker does
+        let input = dag.neighbors_directed(idx, petgraph::Incoming).next().map(|parent| &schemas[&parent]);
+        let schema = apply_op_schema(&dag[idx], input, source_schemas)?;
+        schemas.insert(idx, schema);
+    }
+
+    let sinks: Vec<NodeIndex> = dag.externals(petgraph::Outgoing).collect();
+    match sinks.as_slice() {
+        [sink] => Ok(schemas.remove(sink).unwrap()),
+        _ => Err(SchemaError::AmbiguousOutput(sinks.iter().map(|&idx| dag[idx].id.clone()).collect())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, op: DagOp) -> DagNode {
+        DagNode { id: id.to_string(), op, input_schema: None, output_schema: None }
+    }
+
+    #[test]
+    fn merge_dags_shares_common_source() {
+        let (sample, _) = build_sample_dag();
+        let distinct = build_distinct_countries_dag();
+        let total = sample.node_count() + distinct.node_count();
+
+        let merged = merge_dags(sample, distinct).unwrap();
+        assert_eq!(merged.node_count(), total - 1);
+        let transactions = merged.node_indices().find(|&idx| merged[idx].id == "transactions").unwrap();
+        assert_eq!(merged.neighbors_directed(transactions, petgraph::Outgoing).count(), 2);
+    }
+
+    #[test]
+    fn merge_dags_rejects_cycle() {
+        let mut a = DiGraph::new();
+        let (a1, b1) = (a.add_node(node("a", DagOp::Source)), a.add_node(node("b", DagOp::GroupBySum)));
+        a.add_edge(a1, b1, ());
+        let mut b = DiGraph::new();
+        let (a2, b2) = (b.add_node(node("a", DagOp::Source)), b.add_node(node("b", DagOp::GroupBySum)));
+        b.add_edge(b2, a2, ());
+
+        assert!(matches!(merge_dags(a, b), Err(CycleError::Cycle(_))));
+    }
+
+    #[test]
+    fn detect_cycle_reports_back_edge_ids() {
+        let (mut dag, transactions) = build_sample_dag();
+        assert!(detect_cycle(&dag).is_ok());
+
+        let usd = dag.node_indices().find(|&idx| dag[idx].id == "usd_by_country").unwrap();
+        dag.add_edge(usd, transactions, ());
+        let Err(CycleError::Cycle(ids)) = detect_cycle(&dag) else { panic!("cycle not detected") };
+        assert_eq!(ids.first(), ids.last());
+        let mut members: Vec<&str> = ids[1..].iter().map(|id| id.as_str()).collect();
+        members.sort();
+        assert_eq!(members, vec!["euro_selection", "transactions", "usd_by_country"]);
+    }
+
+    #[test]
+    fn infer_output_schema_follows_ops_and_rejects_cycles() {
+        let sources = HashMap::from([("transactions".to_string(), transactions_schema())]);
+        let (mut dag, transactions) = build_sample_dag();
+        assert_eq!(infer_output_schema(&dag, &sources).unwrap(), usd_by_country_schema());
+
+        let usd = dag.node_indices().find(|&idx| dag[idx].id == "usd_by_country").unwrap();
+        dag.add_edge(usd, transactions, ());
+        assert!(matches!(infer_output_schema(&dag, &sources), Err(SchemaError::Cycle(_))));
+    }
+
+    #[test]
+    fn infer_output_schema_checks_builtin_op_columns() {
+        // A source without the usd column cannot be filtered or summed
+        let narrow = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("country", DataType::Utf8, false),
+        ]);
+        let sources = HashMap::from([("transactions".to_string(), narrow)]);
+        let (dag, _) = build_sample_dag();
+        assert!(matches!(
+            infer_output_schema(&dag, &sources),
+            Err(SchemaError::MissingColumn { node, column }) if node == "euro_selection" && column == "usd"
+        ));
+
+        // filter_country reads its columns by position, so a reordered input is rejected too
+        let mut fields: Vec<Field> = transactions_schema().fields().iter().map(|f| f.as_ref().clone()).collect();
+        fields.reverse();
+        let sources = HashMap::from([("transactions".to_string(), Schema::new(fields))]);
+        assert!(matches!(infer_output_schema(&dag, &sources), Err(SchemaError::SchemaMismatch { .. })));
+
+        let mut dag = DiGraph::new();
+        let source = dag.add_node(node("transactions", DagOp::Source));
+        let sum = dag.add_node(node("sum", DagOp::GroupBySum));
+        dag.add_edge(source, sum, ());
+        let sources = HashMap::from([("transactions".to_string(), usd_by_country_schema())]);
+        assert!(matches!(infer_output_schema(&dag, &sources), Err(SchemaError::MissingColumn { column, .. }) if column == "usd"));
+    }
+}
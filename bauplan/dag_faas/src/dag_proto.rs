@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod dag_proto {
     include!(concat!(env!("OUT_DIR"), "/dag_proto.rs"));
 }
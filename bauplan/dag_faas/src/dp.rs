@@ -1,56 +1,570 @@
-use crate::dag_proto::dag_proto::worker_client::WorkerClient;
-use crate::dag_proto::dag_proto::TaskRequest;
+use crate::dag_proto::dag_proto::{HealthRequest, HealthResponse, TaskRequest, TaskResult};
+use crate::replay::ReplayMode;
+use crate::worker::{GrpcWorkerFactory, ReplayWorker, ReplayWorkerFactory, WorkerCapacity, WorkerFactory};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
 use petgraph::algo::toposort;
-use petgraph::graph::NodeIndex;
-use crate::dag::build_sample_dag;
+use petgraph::graph::{DiGraph, NodeIndex};
+use crate::dag::{build_distinct_countries_dag, build_sample_dag, detect_cycle, infer_output_schema, merge_dags, validate_dag_schemas, CycleError, DagNode};
 use crate::arrow_util::*;
 use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-// Data Plane (DP) - orchestrates the execution of tasks across multiple workers
-pub async fn run_dp(worker_addrs: Vec<&str>) {
-    let (dag, _) = build_sample_dag();
-    let topo = toposort(&dag, None).expect("DAG must be acyclic");
-    let mut node_results: HashMap<NodeIndex, Vec<u8>> = HashMap::new();
+// A task's result only depends on its op and its inputs, so fingerprint those together. Nodes
+// without inputs, such as sources, read data named by their id, so the id is part of theirs.
+fn task_fingerprint(node: &DagNode, input_batches: &[Vec<u8>]) -> Result<u64, ArrowError> {
+    let mut hasher = DefaultHasher::new();
+    node.op.hash(&mut hasher);
+    if input_batches.is_empty() {
+        node.id.hash(&mut hasher);
+    }
+    for bytes in input_batches {
+        batch_fingerprint(&bytes_to_batch(bytes)?)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
 
-    for (i, node_idx) in topo.iter().enumerate() {
-        let node = &dag[*node_idx];
-        let worker_addr = worker_addrs[i % worker_addrs.len()];
+// Inputs above this size go to the worker with the most memory
+const LARGE_INPUT_BYTES: usize = 64 * 1024 * 1024;
 
-        let parent_outputs: Vec<Vec<u8>> = dag
-            .neighbors_directed(*node_idx, petgraph::Incoming)
-            .map(|parent| node_results.get(&parent).cloned().unwrap_or_default())
-            .collect();
+// A worker address together with the capacity it advertised
+#[derive(Debug, Clone)]
+pub struct WorkerHandle {
+    pub addr: String,
+    pub capacity: WorkerCapacity,
+}
+
+// Large inputs go to the workers with the most memory, small ones to the smallest workers,
+// keeping big workers free for the tasks that need them. `turn` round-robins among equal workers.
+// None when there are no workers.
+pub fn select_worker_for_input(workers: &[WorkerHandle], input_size_bytes: usize, turn: usize) -> Option<&WorkerHandle> {
+    let by_memory = |w: &WorkerHandle| (w.capacity.max_memory_mb, w.capacity.cores);
+    let target = if input_size_bytes > LARGE_INPUT_BYTES {
+        workers.iter().map(by_memory).max()
+    } else {
+        workers.iter().map(by_memory).min()
+    }?;
 
-        println!("DP: dispatching node {} to worker {}", node.id, worker_addr);
+    let candidates: Vec<&WorkerHandle> = workers.iter().filter(|w| by_memory(w) == target).collect();
+    Some(candidates[turn % candidates.len()])
+}
 
-        let mut client = WorkerClient::connect(worker_addr.to_string()).await.unwrap();
-        let req = tonic::Request::new(TaskRequest {
-            task_id: node.id.clone(),
-            code: node.code.clone(),
-            input_batches: parent_outputs,
+// Ask every worker for its capacity, retrying workers that are not reachable yet per the retry policy
+async fn discover_workers(
+    factory: &dyn WorkerFactory,
+    worker_addrs: &[String],
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<WorkerHandle>, ExecutionError> {
+    let mut workers = Vec::new();
+    for addr in worker_addrs {
+        let mut attempt = 1;
+        let health = loop {
+            match probe_worker(factory, addr).await {
+                Ok(health) => break health,
+                Err(err) if !err.is_retryable() || attempt >= retry_policy.max_attempts => return Err(err),
+                Err(err) => {
+                    println!("DP: attempt {} to reach worker {} failed: {}", attempt, addr, err);
+                    tokio::time::sleep(retry_policy.backoff).await;
+                    attempt += 1;
+                }
+            }
+        };
+        workers.push(WorkerHandle {
+            addr: addr.clone(),
+            capacity: WorkerCapacity {
+                max_memory_mb: health.max_memory_mb as usize,
+                cores: health.cores as usize,
+            },
         });
-        let resp = client.run_task(req).await.unwrap().into_inner();
-        println!("DP: got result for node {}: {}", node.id, resp.log);
-
-        node_results.insert(*node_idx, resp.output_batch);
-    }
-
-    let last_idx = *topo.last().unwrap();
-    let final_batch = bytes_to_batch(&node_results[&last_idx]);
-    println!("Final result:");
-    for row in 0..final_batch.num_rows() {
-        let country = final_batch
-            .column(0)
-            .as_any()
-            .downcast_ref::<arrow::array::StringArray>()
-            .unwrap()
-            .value(row);
-        let usd_sum = final_batch
-            .column(1)
-            .as_any()
-            .downcast_ref::<arrow::array::Int32Array>()
-            .unwrap()
-            .value(row);
-        println!("country: {}, usd_sum: {}", country, usd_sum);
+    }
+    Ok(workers)
+}
+
+async fn probe_worker(factory: &dyn WorkerFactory, addr: &str) -> Result<HealthResponse, ExecutionError> {
+    let worker = factory.connect(addr).await?;
+    let health = worker
+        .health(tonic::Request::new(HealthRequest {}))
+        .await
+        .map_err(|status| ExecutionError::Health { addr: addr.to_string(), status: Box::new(status) })?;
+    Ok(health.into_inner())
+}
+
+#[derive(Debug)]
+pub enum ExecutionError {
+    Cycle(CycleError),
+    // The executor was given no worker addresses
+    NoWorkers,
+    // Every schema problem found, so a DAG can be fixed in one pass
+    Schema(Vec<SchemaError>),
+    Arrow(ArrowError),
+    Transport(tonic::transport::Error),
+    // A worker did not answer its health check
+    Health { addr: String, status: Box<tonic::Status> },
+    // A worker failed the task on every attempt
+    Worker { task_id: String, status: Box<tonic::Status> },
+    // The replay store could not be read or written
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::Cycle(err) => write!(f, "{}", err),
+            ExecutionError::NoWorkers => write!(f, "no workers to dispatch to"),
+            ExecutionError::Schema(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("; "))
+            }
+            ExecutionError::Arrow(err) => write!(f, "{}", err),
+            ExecutionError::Transport(err) => write!(f, "cannot reach worker: {}", err),
+            ExecutionError::Health { addr, status } => write!(f, "worker {} health check failed: {}", addr, status.message()),
+            ExecutionError::Worker { task_id, status } => write!(f, "task {} failed: {}", task_id, status.message()),
+            ExecutionError::Io(err) => write!(f, "replay store: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+impl ExecutionError {
+    // Only failures to reach a worker may succeed on a later attempt; a worker rejecting the task
+    // would reject it again
+    fn is_retryable(&self) -> bool {
+        match self {
+            ExecutionError::Transport(_) => true,
+            ExecutionError::Health { status, .. } | ExecutionError::Worker { status, .. } => {
+                status.code() == tonic::Code::Unavailable
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<CycleError> for ExecutionError {
+    fn from(err: CycleError) -> Self {
+        ExecutionError::Cycle(err)
+    }
+}
+
+impl From<SchemaError> for ExecutionError {
+    fn from(err: SchemaError) -> Self {
+        ExecutionError::Schema(vec![err])
+    }
+}
+
+impl From<ArrowError> for ExecutionError {
+    fn from(err: ArrowError) -> Self {
+        ExecutionError::Arrow(err)
+    }
+}
+
+impl From<tonic::transport::Error> for ExecutionError {
+    fn from(err: tonic::transport::Error) -> Self {
+        ExecutionError::Transport(err)
+    }
+}
+
+impl From<std::io::Error> for ExecutionError {
+    fn from(err: std::io::Error) -> Self {
+        ExecutionError::Io(err)
+    }
+}
+
+// How often a failed task is retried, and how long to wait between attempts
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { max_attempts: 3, backoff: Duration::from_millis(100) }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExecutionMetrics {
+    pub tasks_dispatched: usize,
+    pub tasks_cached: usize,
+    pub retries: usize,
+}
+
+// Control plane state: the workers to dispatch to, how to record or replay, and run metrics
+pub struct DAGExecutor {
+    factory: Box<dyn WorkerFactory>,
+    worker_addrs: Vec<String>,
+    // Discovered on the first run, so discovery follows the configured retry policy
+    workers: Vec<WorkerHandle>,
+    // Advances on every dispatch to spread tasks over equal workers
+    turn: AtomicUsize,
+    replay: ReplayMode,
+    retry_policy: RetryPolicy,
+    metrics: Arc<Mutex<ExecutionMetrics>>,
+}
+
+impl DAGExecutor {
+    // A replayed run is served by a worker holding the recorded results instead of real workers
+    pub fn connect(worker_addrs: &[&str], replay: ReplayMode) -> Result<Self, ExecutionError> {
+        if let ReplayMode::Replay(store) = &replay {
+            let factory = ReplayWorkerFactory::new(ReplayWorker::from_replay_store(store)?);
+            return Self::with_factory(Box::new(factory), &["replay"], replay);
+        }
+        Self::with_factory(Box::new(GrpcWorkerFactory), worker_addrs, replay)
+    }
+
+    // Run tasks through the given factory; worker capacities are discovered on the first run
+    pub fn with_factory(
+        factory: Box<dyn WorkerFactory>,
+        worker_addrs: &[&str],
+        replay: ReplayMode,
+    ) -> Result<Self, ExecutionError> {
+        if worker_addrs.is_empty() {
+            return Err(ExecutionError::NoWorkers);
+        }
+        Ok(DAGExecutor {
+            factory,
+            worker_addrs: worker_addrs.iter().map(|addr| addr.to_string()).collect(),
+            workers: Vec::new(),
+            turn: AtomicUsize::new(0),
+            replay,
+            retry_policy: RetryPolicy::default(),
+            metrics: Arc::new(Mutex::new(ExecutionMetrics::default())),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<Mutex<ExecutionMetrics>> {
+        self.metrics.clone()
+    }
+
+    // Run every node in topological order and return the batch of each sink node by id
+    pub async fn run(&mut self, dag: &DiGraph<DagNode, ()>) -> Result<HashMap<String, RecordBatch>, ExecutionError> {
+        detect_cycle(dag)?;
+        validate_dag_schemas(dag).map_err(ExecutionError::Schema)?;
+        if self.workers.is_empty() {
+            self.workers = discover_workers(self.factory.as_ref(), &self.worker_addrs, &self.retry_policy).await?;
+        }
+        let topo = toposort(dag, None).expect("DAG must be acyclic");
+        let mut node_results: HashMap<NodeIndex, Vec<u8>> = HashMap::new();
+        let mut result_cache: HashMap<u64, Vec<u8>> = HashMap::new();
+
+        for node_idx in topo.iter() {
+            let node = &dag[*node_idx];
+
+            let parent_outputs: Vec<Vec<u8>> = dag
+                .neighbors_directed(*node_idx, petgraph::Incoming)
+                .map(|parent| node_results.get(&parent).cloned().unwrap_or_default())
+                .collect();
+
+            let fingerprint = task_fingerprint(node, &parent_outputs)?;
+            if let Some(cached) = result_cache.get(&fingerprint) {
+                println!("DP: reusing cached result for node {}", node.id);
+                self.metrics.lock().unwrap().tasks_cached += 1;
+                node_results.insert(*node_idx, cached.clone());
+                continue;
+            }
+
+            let req = TaskRequest {
+                task_id: node.id.clone(),
+                input_batches: parent_outputs,
+                op: Some(node.op.clone().into()),
+            };
+            let resp = self.dispatch(&req).await?;
+
+            if let ReplayMode::Record(store) = &self.replay {
+                store.record(&node.id, &req, &resp)?;
+            }
+
+            // Catch a worker returning a different schema than the node declares
+            if let Some(schema) = &node.output_schema {
+                bytes_to_batch_with_schema(&resp.output_batch, schema)?;
+            }
+
+            result_cache.insert(fingerprint, resp.output_batch.clone());
+            node_results.insert(*node_idx, resp.output_batch);
+        }
+
+        let mut outputs = HashMap::new();
+        for sink in dag.externals(petgraph::Outgoing) {
+            outputs.insert(dag[sink].id.clone(), bytes_to_batch(&node_results[&sink])?);
+        }
+        Ok(outputs)
+    }
+
+    // Send a task to the worker chosen for its input size, retrying unreachable workers per the retry policy
+    async fn dispatch(&self, req: &TaskRequest) -> Result<TaskResult, ExecutionError> {
+        let input_size: usize = req.input_batches.iter().map(|b| b.len()).sum();
+        let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+        let worker = select_worker_for_input(&self.workers, input_size, turn).ok_or(ExecutionError::NoWorkers)?;
+        let worker_addr = &worker.addr;
+
+        let mut attempt = 1;
+        loop {
+            println!("DP: dispatching node {} to worker {}", req.task_id, worker_addr);
+            self.metrics.lock().unwrap().tasks_dispatched += 1;
+            let result = match self.factory.connect(worker_addr).await {
+                Ok(worker) => worker
+                    .run_task(tonic::Request::new(req.clone()))
+                    .await
+                    .map_err(|status| ExecutionError::Worker { task_id: req.task_id.clone(), status: Box::new(status) }),
+                Err(err) => Err(err.into()),
+            };
+
+            match result {
+                Ok(resp) => {
+                    let resp = resp.into_inner();
+                    println!("DP: got result for node {}: {}", req.task_id, resp.log);
+                    return Ok(resp);
+                }
+                Err(err) if !err.is_retryable() || attempt >= self.retry_policy.max_attempts => return Err(err),
+                Err(err) => {
+                    println!("DP: attempt {} for node {} failed: {}", attempt, req.task_id, err);
+                    self.metrics.lock().unwrap().retries += 1;
+                    tokio::time::sleep(self.retry_policy.backoff).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+// Data Plane (DP) - builds the sample queries and runs them on the executor
+pub async fn run_dp(executor: &mut DAGExecutor) {
+    let (sample_dag, _) = build_sample_dag();
+    let queries = vec![sample_dag, build_distinct_countries_dag()];
+
+    // Report what each query will return before running anything
+    let source_schemas = HashMap::from([("transactions".to_string(), transactions_schema())]);
+    for query in &queries {
+        match infer_output_schema(query, &source_schemas) {
+            Ok(schema) => println!("DP: query output schema {:?}", schema.fields()),
+            Err(error) => {
+                println!("DP: schema error: {}", error);
+                return;
+            }
+        }
+    }
+
+    // Both queries read the transactions source; merging runs it once
+    let dag = match queries.into_iter().try_fold(DiGraph::new(), merge_dags) {
+        Ok(dag) => dag,
+        Err(error) => {
+            println!("DP: cannot merge queries: {}", error);
+            return;
+        }
+    };
+    let outputs = match executor.run(&dag).await {
+        Ok(outputs) => outputs,
+        Err(error) => {
+            println!("DP: execution failed: {}", error);
+            return;
+        }
+    };
+
+    let mut sinks: Vec<&String> = outputs.keys().collect();
+    sinks.sort();
+    for sink in sinks {
+        println!("Final result of {}:", sink);
+        print_batch(&outputs[sink]);
+    }
+    println!("DP: {:?}", executor.metrics().lock().unwrap());
+}
+
+// Print each row as "column: value, ..."
+fn print_batch(batch: &arrow::record_batch::RecordBatch) {
+    use arrow::util::display::{ArrayFormatter, FormatOptions};
+    let options = FormatOptions::default();
+    let formatters: Vec<ArrayFormatter> = batch
+        .columns()
+        .iter()
+        .map(|c| ArrayFormatter::try_new(c.as_ref(), &options).unwrap())
+        .collect();
+    for row in 0..batch.num_rows() {
+        let values: Vec<String> = batch
+            .schema()
+            .fields()
+            .iter()
+            .zip(formatters.iter())
+            .map(|(field, formatter)| format!("{}: {}", field.name(), formatter.value(row)))
+            .collect();
+        println!("{}", values.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dag::DagOp;
+    use crate::dag_proto::dag_proto::worker_server::Worker;
+    use crate::dag_proto::dag_proto::HealthResponse;
+    use tonic::{Request, Response, Status};
+
+    // Answers tasks with pre-programmed output batches and records the order tasks arrive in
+    struct MockWorker {
+        responses: HashMap<String, Vec<u8>>,
+        received: Mutex<Vec<String>>,
+        // Health checks left to answer as unavailable, as if the worker were still starting
+        starting_checks: Mutex<usize>,
+    }
+
+    #[tonic::async_trait]
+    impl Worker for MockWorker {
+        async fn run_task(&self, request: Request<TaskRequest>) -> Result<Response<TaskResult>, Status> {
+            let req = request.into_inner();
+            self.received.lock().unwrap().push(req.task_id.clone());
+            let output_batch = self.responses.get(&req.task_id).cloned().ok_or_else(|| Status::not_found(req.task_id.clone()))?;
+            Ok(Response::new(TaskResult { task_id: req.task_id, log: "mock".to_string(), output_batch }))
+        }
+
+        async fn health(&self, _request: Request<HealthRequest>) -> Result<Response<HealthResponse>, Status> {
+            let mut starting_checks = self.starting_checks.lock().unwrap();
+            if *starting_checks > 0 {
+                *starting_checks -= 1;
+                return Err(Status::unavailable("starting"));
+            }
+            Ok(Response::new(HealthResponse { max_memory_mb: 1024, cores: 1 }))
+        }
+    }
+
+    struct MockWorkerFactory(Arc<MockWorker>);
+
+    #[tonic::async_trait]
+    impl WorkerFactory for MockWorkerFactory {
+        async fn connect(&self, _addr: &str) -> Result<Arc<dyn Worker>, tonic::transport::Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn mock_worker(responses: Vec<(&str, RecordBatch)>) -> Arc<MockWorker> {
+        Arc::new(MockWorker {
+            responses: responses.into_iter().map(|(id, batch)| (id.to_string(), batch_to_bytes(&batch))).collect(),
+            received: Mutex::new(Vec::new()),
+            starting_checks: Mutex::new(0),
+        })
+    }
+
+    async fn mock_executor(worker: &Arc<MockWorker>, worker_addrs: &[&str]) -> DAGExecutor {
+        let factory = Box::new(MockWorkerFactory(worker.clone()));
+        DAGExecutor::with_factory(factory, worker_addrs, ReplayMode::Off).unwrap()
+    }
+
+    #[tokio::test]
+    async fn three_node_dag_runs_in_topological_order() {
+        let filtered = filter_country(&make_sample_batch(), "IT");
+        let summed = groupby_sum(&filtered).unwrap();
+        let worker = mock_worker(vec![
+            ("transactions", make_sample_batch()),
+            ("euro_selection", filtered),
+            ("usd_by_country", summed.clone()),
+        ]);
+        let mut executor = mock_executor(&worker, &["a", "b"]).await;
+
+        let (dag, _) = build_sample_dag();
+        let outputs = executor.run(&dag).await.unwrap();
+
+        assert_eq!(*worker.received.lock().unwrap(), vec!["transactions", "euro_selection", "usd_by_country"]);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs["usd_by_country"], summed);
+        assert_eq!(executor.metrics().lock().unwrap().tasks_dispatched, 3);
+    }
+
+    fn node(id: &str, op: DagOp) -> DagNode {
+        DagNode { id: id.to_string(), op, input_schema: None, output_schema: None }
+    }
+
+    #[test]
+    fn fingerprint_separates_sources_and_shares_equal_tasks() {
+        let transactions = task_fingerprint(&node("transactions", DagOp::Source), &[]).unwrap();
+        let customers = task_fingerprint(&node("customers", DagOp::Source), &[]).unwrap();
+        assert_ne!(transactions, customers);
+
+        // The same op on the same input is the same task, whatever the node is called
+        let input = vec![batch_to_bytes(&make_sample_batch())];
+        let a = task_fingerprint(&node("a", DagOp::GroupBySum), &input).unwrap();
+        let b = task_fingerprint(&node("b", DagOp::GroupBySum), &input).unwrap();
+        assert_eq!(a, b);
+    }
+
+    fn worker(addr: &str, max_memory_mb: usize) -> WorkerHandle {
+        WorkerHandle { addr: addr.to_string(), capacity: WorkerCapacity { max_memory_mb, cores: 4 } }
+    }
+
+    #[test]
+    fn select_worker_by_input_size() {
+        let workers = vec![worker("small", 1024), worker("large", 16384)];
+        assert_eq!(select_worker_for_input(&workers, 1024 * 1024, 0).unwrap().addr, "small");
+        assert_eq!(select_worker_for_input(&workers, 100 * 1024 * 1024, 0).unwrap().addr, "large");
+        assert!(select_worker_for_input(&[], 1024, 0).is_none());
+    }
+
+    #[test]
+    fn select_worker_round_robins_equal_workers() {
+        let workers = vec![worker("a", 1024), worker("b", 1024), worker("large", 16384)];
+        let picked: Vec<&str> = (0..4).map(|turn| select_worker_for_input(&workers, 1024, turn).unwrap().addr.as_str()).collect();
+        assert_eq!(picked, vec!["a", "b", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn executor_needs_workers() {
+        let factory = Box::new(MockWorkerFactory(mock_worker(vec![])));
+        let error = DAGExecutor::with_factory(factory, &[], ReplayMode::Off).err();
+        assert!(matches!(error, Some(ExecutionError::NoWorkers)));
+    }
+
+    #[tokio::test]
+    async fn rejected_tasks_are_not_retried() {
+        let worker = mock_worker(vec![]);
+        let mut executor = mock_executor(&worker, &["a"]).await;
+
+        let (dag, _) = build_sample_dag();
+        let error = executor.run(&dag).await.unwrap_err();
+
+        assert!(matches!(&error, ExecutionError::Worker { task_id, status } if task_id == "transactions" && status.code() == tonic::Code::NotFound));
+        let metrics = executor.metrics().lock().unwrap().clone();
+        assert_eq!((metrics.tasks_dispatched, metrics.retries), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn every_schema_error_is_reported() {
+        let (mut dag, _) = build_sample_dag();
+        for idx in dag.node_indices() {
+            if dag[idx].id != "transactions" {
+                dag[idx].input_schema = Some(usd_by_country_schema());
+            }
+        }
+        let worker = mock_worker(vec![]);
+        let mut executor = mock_executor(&worker, &["a"]).await;
+
+        let error = executor.run(&dag).await.unwrap_err();
+        assert!(matches!(error, ExecutionError::Schema(errors) if errors.len() == 2));
+        assert!(worker.received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn discovery_waits_for_starting_workers() {
+        let worker = mock_worker(vec![("transactions", make_sample_batch())]);
+        *worker.starting_checks.lock().unwrap() = 2;
+        let policy = RetryPolicy { max_attempts: 3, backoff: Duration::ZERO };
+        let mut executor = mock_executor(&worker, &["a"]).await.with_retry_policy(policy.clone());
+
+        let mut dag = DiGraph::new();
+        dag.add_node(node("transactions", DagOp::Source));
+        assert!(executor.run(&dag).await.is_ok());
+
+        // A worker that is still unavailable after every attempt is named in the error
+        let worker = mock_worker(vec![]);
+        *worker.starting_checks.lock().unwrap() = 3;
+        let mut executor = mock_executor(&worker, &["a"]).await.with_retry_policy(policy);
+        let error = executor.run(&dag).await.unwrap_err();
+        assert!(matches!(&error, ExecutionError::Health { addr, .. } if addr == "a"));
+        assert_eq!(error.to_string(), "worker a health check failed: starting");
     }
 }
@@ -0,0 +1,6 @@
+pub mod arrow_util;
+pub mod dag;
+pub mod dag_proto;
+pub mod dp;
+pub mod replay;
+pub mod worker;
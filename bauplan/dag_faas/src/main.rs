@@ -1,27 +1,44 @@
+use dag_faas::{dp, replay, worker};
 use std::env;
 
-mod arrow_util;
-mod dag;
-mod worker;
-mod dag_proto;
-mod dp;
-
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        println!("Usage: {} [dp|worker <addr>]", args[0]);
+        println!(
+            "Usage: {} [dp [--record-dir <dir>|--replay-dir <dir>]|worker <addr> [max_memory_mb] [cores]]",
+            args[0]
+        );
         return;
     }
     match args[1].as_str() {
         "worker" => {
             let addr = args.get(2).map(|s| s.as_str()).unwrap_or("127.0.0.1:50051");
-            println!("Starting worker at {}", addr);
-            worker::serve_worker(addr).await;
+            let mut capacity = worker::WorkerCapacity::default();
+            if let Some(mb) = args.get(3) {
+                capacity.max_memory_mb = mb.parse().expect("max_memory_mb must be a number");
+            }
+            if let Some(cores) = args.get(4) {
+                capacity.cores = cores.parse().expect("cores must be a number");
+            }
+            println!("Starting worker at {} with {:?}", addr, capacity);
+            worker::serve_worker(addr, capacity).await;
         }
         "dp" => {
             let workers = vec!["http://127.0.0.1:50051", "http://127.0.0.1:50052"];
-            dp::run_dp(workers).await;
+            let mode = match (args.get(2).map(|s| s.as_str()), args.get(3)) {
+                (Some("--record-dir"), Some(dir)) => replay::ReplayMode::Record(replay::ReplayStore::new(dir)),
+                (Some("--replay-dir"), Some(dir)) => replay::ReplayMode::Replay(replay::ReplayStore::new(dir)),
+                _ => replay::ReplayMode::Off,
+            };
+            let mut executor = match dp::DAGExecutor::connect(&workers, mode) {
+                Ok(executor) => executor.with_retry_policy(dp::RetryPolicy::default()),
+                Err(error) => {
+                    println!("Cannot start data plane: {}", error);
+                    return;
+                }
+            };
+            dp::run_dp(&mut executor).await;
         }
         _ => {
             println!("Unknown command");
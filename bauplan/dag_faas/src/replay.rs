@@ -0,0 +1,56 @@
+use crate::dag_proto::dag_proto::{TaskRequest, TaskResult};
+use prost::Message;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Records task requests and results on disk so a run can be replayed without workers
+pub struct ReplayStore {
+    pub path: PathBuf,
+}
+
+// Whether the data plane records tasks, replays them, or neither
+pub enum ReplayMode {
+    Off,
+    Record(ReplayStore),
+    Replay(ReplayStore),
+}
+
+impl ReplayStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ReplayStore { path: path.into() }
+    }
+
+    fn request_path(&self, task_id: &str) -> PathBuf {
+        self.path.join(format!("{}.request.pb", task_id))
+    }
+
+    fn result_path(&self, task_id: &str) -> PathBuf {
+        self.path.join(format!("{}.result.pb", task_id))
+    }
+
+    // Protobuf-encode the request and result of a task into the store directory
+    pub fn record(&self, task_id: &str, req: &TaskRequest, resp: &TaskResult) -> io::Result<()> {
+        fs::create_dir_all(&self.path)?;
+        fs::write(self.request_path(task_id), req.encode_to_vec())?;
+        fs::write(self.result_path(task_id), resp.encode_to_vec())
+    }
+
+    // Ids of every task recorded in the store
+    pub fn task_ids(&self) -> io::Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(id) = name.strip_suffix(".result.pb") {
+                ids.push(id.to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn replay(&self, task_id: &str) -> Option<(TaskRequest, TaskResult)> {
+        let req = TaskRequest::decode(fs::read(self.request_path(task_id)).ok()?.as_slice()).ok()?;
+        let resp = TaskResult::decode(fs::read(self.result_path(task_id)).ok()?.as_slice()).ok()?;
+        Some((req, resp))
+    }
+}
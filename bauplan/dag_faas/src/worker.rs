@@ -1,40 +1,239 @@
+use tonic::transport::Channel;
 use tonic::{Request, Response, Status};
+use crate::dag_proto::dag_proto::worker_client::WorkerClient;
 use crate::dag_proto::dag_proto::worker_server::{Worker, WorkerServer};
-use crate::dag_proto::dag_proto::{TaskRequest, TaskResult};
+use crate::dag_proto::dag_proto::{HealthRequest, HealthResponse, TaskRequest, TaskResult};
 use crate::arrow_util::*;
+use crate::dag::DagOp;
+use crate::replay::ReplayStore;
+use arrow::array::Int64Array;
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
 
-pub struct MyWorker {}
+// Resources a worker advertises to the data plane
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorkerCapacity {
+    pub max_memory_mb: usize,
+    pub cores: usize,
+}
+
+impl Default for WorkerCapacity {
+    fn default() -> Self {
+        WorkerCapacity {
+            max_memory_mb: 1024,
+            cores: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+}
+
+pub struct MyWorker {
+    capacity: WorkerCapacity,
+}
 
 #[tonic::async_trait]
 impl Worker for MyWorker {
     async fn run_task(&self, request: Request<TaskRequest>) -> Result<Response<TaskResult>, Status> {
         let req = request.into_inner();
-        println!("Worker: received task {} code {}", req.task_id, req.code);
+        let op: DagOp = req.op.map(DagOp::from).ok_or_else(|| Status::invalid_argument("task has no op"))?;
+        println!("Worker: received task {} op {}", req.task_id, op);
 
-        let input_batches: Vec<_> = req.input_batches.iter().map(|b| bytes_to_batch(b)).collect();
+        let input_batches = req
+            .input_batches
+            .iter()
+            .map(|b| bytes_to_batch(b))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Status::invalid_argument(format!("malformed input batch: {}", e)))?;
 
-        let output_batch = match req.code.as_str() {
-            "source" => make_sample_batch(),
-            "filter_country" => filter_country(&input_batches[0], "IT"),
-            "groupby_sum" => groupby_sum(&input_batches[0]),
-            _ => panic!("Unknown code"),
+        let input = || {
+            input_batches
+                .first()
+                .ok_or_else(|| ArrowError::InvalidArgumentError(format!("{} needs an input batch", op)))
         };
+        let output_batch = match &op {
+            DagOp::Source => Ok(make_sample_batch()),
+            DagOp::FilterCountry(country) => input().map(|batch| filter_country(batch, country)),
+            DagOp::GroupBySum => input().and_then(groupby_sum),
+            DagOp::Custom(code) => run_custom_op(code, &input_batches),
+        }
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
         let output_bytes = batch_to_bytes(&output_batch);
 
         Ok(Response::new(TaskResult {
             task_id: req.task_id,
-            log: format!("Worker finished {}", req.code),
+            log: format!("Worker finished {}", op),
             output_batch: output_bytes,
         }))
     }
+
+    async fn health(&self, _request: Request<HealthRequest>) -> Result<Response<HealthResponse>, Status> {
+        Ok(Response::new(HealthResponse {
+            max_memory_mb: self.capacity.max_memory_mb as u64,
+            cores: self.capacity.cores as u32,
+        }))
+    }
+}
+
+// Custom ops look like "op" or "op:arg1:arg2", e.g. "str_upper:country"
+fn run_custom_op(code: &str, input_batches: &[RecordBatch]) -> Result<RecordBatch, ArrowError> {
+    let mut parts = code.split(':');
+    let op = parts.next().unwrap_or_default();
+    let args: Vec<&str> = parts.collect();
+
+    let invalid = |message: String| ArrowError::InvalidArgumentError(format!("{}: {}", code, message));
+    let arg = |i: usize| args.get(i).copied().ok_or_else(|| invalid(format!("missing argument {}", i + 1)));
+    let parsed = |i: usize| -> Result<i64, ArrowError> {
+        let value = arg(i)?;
+        value.parse().map_err(|_| invalid(format!("argument {} is not a number", value)))
+    };
+    let unit = |i: usize| -> Result<DateUnit, ArrowError> { arg(i)?.parse().map_err(invalid) };
+    let input = || input_batches.first().ok_or_else(|| invalid("no input batch".to_string()));
+
+    match op {
+        "str_upper" => str_upper(input()?, arg(0)?),
+        "str_lower" => str_lower(input()?, arg(0)?),
+        "str_trim" => str_trim(input()?, arg(0)?),
+        "str_substr" => {
+            let length = parsed(2)?;
+            let length = u64::try_from(length).map_err(|_| invalid(format!("negative length {}", length)))?;
+            str_substr(input()?, arg(0)?, parsed(1)?, length)
+        }
+        "date_diff" => date_diff(input()?, arg(0)?, arg(1)?, unit(2)?),
+        "date_add" => date_add(input()?, arg(0)?, parsed(1)?, unit(2)?),
+        "columnar_groupby_sum" => columnar_groupby_sum(input()?, arg(0)?, arg(1)?, arg(2)?),
+        "window_sum" => window_sum(input()?, arg(0)?, arg(1)?, arg(2)?),
+        "row_number" => row_number(input()?, arg(0)?, arg(1)?, arg(2)?),
+        "approx_count_distinct" => {
+            let count = approx_count_distinct(input_batches, arg(0)?)? as i64;
+            let schema = Arc::new(Schema::new(vec![Field::new("approx_count_distinct", DataType::Int64, false)]));
+            RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![count]))])
+        }
+        _ => Err(invalid("unknown op".to_string())),
+    }
+}
+
+// Hands the data plane something that runs tasks for a worker address
+#[tonic::async_trait]
+pub trait WorkerFactory: Send + Sync {
+    async fn connect(&self, addr: &str) -> Result<Arc<dyn Worker>, tonic::transport::Error>;
+}
+
+// Forwards tasks to a worker process over gRPC
+pub struct RemoteWorker {
+    client: WorkerClient<Channel>,
+}
+
+#[tonic::async_trait]
+impl Worker for RemoteWorker {
+    async fn run_task(&self, request: Request<TaskRequest>) -> Result<Response<TaskResult>, Status> {
+        self.client.clone().run_task(request).await
+    }
+
+    async fn health(&self, request: Request<HealthRequest>) -> Result<Response<HealthResponse>, Status> {
+        self.client.clone().health(request).await
+    }
+}
+
+pub struct GrpcWorkerFactory;
+
+#[tonic::async_trait]
+impl WorkerFactory for GrpcWorkerFactory {
+    async fn connect(&self, addr: &str) -> Result<Arc<dyn Worker>, tonic::transport::Error> {
+        let client = WorkerClient::connect(addr.to_string()).await?;
+        Ok(Arc::new(RemoteWorker { client }))
+    }
 }
 
-pub async fn serve_worker(addr: &str) {
-    let worker = MyWorker {};
+// Answers each task with the output batch recorded for its task id, without any network I/O
+pub struct ReplayWorker {
+    responses: HashMap<String, Vec<u8>>,
+}
+
+impl ReplayWorker {
+    pub fn from_replay_store(store: &ReplayStore) -> io::Result<Self> {
+        let mut responses = HashMap::new();
+        for task_id in store.task_ids()? {
+            let (_, resp) = store
+                .replay(&task_id)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("cannot decode task {}", task_id)))?;
+            responses.insert(task_id, resp.output_batch);
+        }
+        Ok(ReplayWorker { responses })
+    }
+}
+
+#[tonic::async_trait]
+impl Worker for ReplayWorker {
+    async fn run_task(&self, request: Request<TaskRequest>) -> Result<Response<TaskResult>, Status> {
+        let req = request.into_inner();
+        let output_batch = self
+            .responses
+            .get(&req.task_id)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("task {} was not recorded", req.task_id)))?;
+        Ok(Response::new(TaskResult {
+            log: format!("Replayed recorded result of {}", req.task_id),
+            task_id: req.task_id,
+            output_batch,
+        }))
+    }
+
+    async fn health(&self, _request: Request<HealthRequest>) -> Result<Response<HealthResponse>, Status> {
+        let capacity = WorkerCapacity::default();
+        Ok(Response::new(HealthResponse {
+            max_memory_mb: capacity.max_memory_mb as u64,
+            cores: capacity.cores as u32,
+        }))
+    }
+}
+
+// Every address connects to the same replay worker
+pub struct ReplayWorkerFactory {
+    worker: Arc<ReplayWorker>,
+}
+
+impl ReplayWorkerFactory {
+    pub fn new(worker: ReplayWorker) -> Self {
+        ReplayWorkerFactory { worker: Arc::new(worker) }
+    }
+}
+
+#[tonic::async_trait]
+impl WorkerFactory for ReplayWorkerFactory {
+    async fn connect(&self, _addr: &str) -> Result<Arc<dyn Worker>, tonic::transport::Error> {
+        Ok(self.worker.clone())
+    }
+}
+
+pub async fn serve_worker(addr: &str, capacity: WorkerCapacity) {
+    let worker = MyWorker { capacity };
     tonic::transport::Server::builder()
         .add_service(WorkerServer::new(worker))
         .serve(addr.parse().unwrap())
         .await
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_custom_ops_are_errors() {
+        let batch = make_sample_batch();
+        for code in ["str_upper", "str_substr:country:x:1", "str_substr:country:0:-1", "str_upper:nope", "no_such_op:country"] {
+            assert!(run_custom_op(code, std::slice::from_ref(&batch)).is_err(), "{} should fail", code);
+        }
+        assert!(run_custom_op("str_upper:country", &[]).is_err());
+    }
+
+    #[test]
+    fn str_upper_dispatch() {
+        let batch = run_custom_op("str_upper:country", &[make_sample_batch()]).unwrap();
+        assert_eq!(batch.schema(), make_sample_batch().schema());
+    }
+}